@@ -1,90 +1,292 @@
-use crate::bio_format::bam::{from_bam_inner, from_sam_inner};
-use crate::bio_format::bcf::{from_bcf_inner, from_vcf_inner};
+use crate::bio_format::bam::{
+    from_bam_inner, from_sam_inner, nuon_to_bam, nuon_to_sam, query_bam_inner,
+};
+use crate::bio_format::bcf::{
+    from_bcf_inner, from_vcf_inner, nuon_to_bcf, nuon_to_vcf, query_bcf_inner, query_vcf_inner,
+};
 use crate::bio_format::bed::from_bed_inner;
 use crate::bio_format::cram::from_cram_inner;
-use crate::bio_format::fasta::{from_fasta_inner, from_fastq_inner, nuon_to_fasta, nuon_to_fastq};
+use crate::bio_format::fasta::{
+    fai_to_nuon, faidx_inner, from_fasta_inner, from_fastq_inner, nuon_to_fasta, nuon_to_fastq,
+    open_indexed_fasta, read_fai, read_reference,
+};
 use crate::bio_format::gfa::from_gfa_inner;
-use crate::bio_format::gff::from_gff_inner;
-use crate::bio_format::Compression;
-use nu_plugin::EvaluatedCall;
-use nu_protocol::{LabeledError, Value};
+use crate::bio_format::gff::{from_gff_inner, from_gtf_inner, stream_gff_inner};
+use crate::bio_format::{input_to_reader, parse_region, regions_from, Compression, ValueStream};
+use nu_plugin::{EngineInterface, EvaluatedCall};
+use nu_protocol::{
+    record, DataSource, LabeledError, ListStream, PipelineData, Signals, Spanned, Value,
+};
+use std::path::PathBuf;
+
+/// Hand a stream of parsed records back to nushell.
+fn list_stream(records: ValueStream, call: &EvaluatedCall, signals: &Signals) -> PipelineData {
+    PipelineData::ListStream(ListStream::new(records, call.head, signals.clone()), None)
+}
+
+/// Formats with a header stream their body, or with `--header`, are returned as a record of
+/// the header and the body, which has to be read into memory.
+fn header_and_body(
+    call: &EvaluatedCall,
+    (header, body): (Value, ValueStream),
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    if !call.has_flag("header")? {
+        return Ok(list_stream(body, call, engine.signals()));
+    }
+
+    let body = body
+        .map(|v| match v {
+            Value::Error { error, .. } => Err(LabeledError::from_diagnostic(&*error)),
+            v => Ok(v),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(PipelineData::Value(
+        Value::record(
+            record! {
+                "header" => header,
+                "body" => Value::list(body, call.head),
+            },
+            call.head,
+        ),
+        None,
+    ))
+}
+
+/// Region queries seek through the file, so need to know which file the input came from.
+fn indexed_input_path(call: &EvaluatedCall, input: &PipelineData) -> Result<PathBuf, LabeledError> {
+    match input.metadata().map(|m| m.data_source) {
+        Some(DataSource::FilePath(path)) => Ok(path),
+        _ => Err(
+            LabeledError::new("region queries need the input to be opened from a file")
+                .with_label("Input is not a file.", call.head)
+                .with_help("open the file with `open --raw`, e.g. `open --raw sample.bam | from bam --region chr1:1-1000`"),
+        ),
+    }
+}
+
+/// Resolve a path passed to a flag against the current directory of the shell.
+fn flag_path(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+    name: &str,
+) -> Result<Option<PathBuf>, LabeledError> {
+    match call.get_flag::<String>(name)? {
+        Some(path) => Ok(Some(PathBuf::from(engine.get_current_dir()?).join(path))),
+        None => Ok(None),
+    }
+}
+
+/// Extract regions from an indexed FASTA, or return its index if no regions are given.
+pub fn faidx(call: &EvaluatedCall, engine: &EngineInterface) -> Result<PipelineData, LabeledError> {
+    let path = PathBuf::from(engine.get_current_dir()?).join(call.req::<String>(0)?);
+    let index = flag_path(call, engine, "index")?;
+    let index = read_fai(call, &path, index, call.has_flag("build")?)?;
+
+    let regions = call
+        .rest::<Spanned<String>>(1)?
+        .iter()
+        .map(|region| parse_region(&region.item, region.span))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if regions.is_empty() {
+        return Ok(PipelineData::Value(fai_to_nuon(&index, call.head), None));
+    }
+
+    let reader = open_indexed_fasta(call, &path, index)?;
+    let reverse = call.has_flag("reverse-complement")?;
+    Ok(list_stream(
+        faidx_inner(call, reader, regions, reverse),
+        call,
+        engine.signals(),
+    ))
+}
 
 pub fn from_fasta(
     call: &EvaluatedCall,
-    input: &Value,
+    input: PipelineData,
     gz: &Compression,
-) -> Result<Value, LabeledError> {
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    let input = input_to_reader(call, input)?;
     let value_records = from_fasta_inner(call, input, gz)?;
 
-    Ok(Value::list(value_records, call.head))
+    Ok(list_stream(value_records, call, engine.signals()))
 }
 
-pub fn to_fasta(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    nuon_to_fasta(call, input)
+pub fn to_fasta(call: &EvaluatedCall, input: PipelineData) -> Result<PipelineData, LabeledError> {
+    let input = input.into_value(call.head)?;
+    nuon_to_fasta(call, &input).map(|v| PipelineData::Value(v, None))
 }
 
 /// Parsing a fastq into Nushell.
 pub fn from_fastq(
     call: &EvaluatedCall,
-    input: &Value,
+    input: PipelineData,
     gz: &Compression,
-) -> Result<Value, LabeledError> {
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    let input = input_to_reader(call, input)?;
     let value_records = from_fastq_inner(call, input, gz)?;
-    Ok(Value::list(value_records, call.head))
+    Ok(list_stream(value_records, call, engine.signals()))
 }
 
 /// Structured data to fastq
-pub fn to_fastq(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    nuon_to_fastq(call, input)
+pub fn to_fastq(call: &EvaluatedCall, input: PipelineData) -> Result<PipelineData, LabeledError> {
+    let input = input.into_value(call.head)?;
+    nuon_to_fastq(call, &input).map(|v| PipelineData::Value(v, None))
 }
 
 /// These B(S)AM functions are quite slow at the moment.
-pub fn from_bam(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    from_bam_inner(call, input)
+pub fn from_bam(
+    call: &EvaluatedCall,
+    input: PipelineData,
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    if let Some(regions) = regions_from(call)? {
+        let path = indexed_input_path(call, &input)?;
+        let index = flag_path(call, engine, "index")?;
+        return header_and_body(call, query_bam_inner(call, &path, index, regions)?, engine);
+    }
+
+    let input = input_to_reader(call, input)?;
+    header_and_body(call, from_bam_inner(call, input)?, engine)
 }
 /// These B(S)AM functions are quite slow at the moment.
-pub fn from_sam(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    from_sam_inner(call, input)
+pub fn from_sam(
+    call: &EvaluatedCall,
+    input: PipelineData,
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    let input = input_to_reader(call, input)?;
+    header_and_body(call, from_sam_inner(call, input)?, engine)
+}
+
+/// Structured alignments to SAM.
+pub fn to_sam(call: &EvaluatedCall, input: PipelineData) -> Result<PipelineData, LabeledError> {
+    let input = input.into_value(call.head)?;
+    nuon_to_sam(call, &input).map(|v| PipelineData::Value(v, None))
+}
+
+/// Structured alignments to BAM.
+pub fn to_bam(call: &EvaluatedCall, input: PipelineData) -> Result<PipelineData, LabeledError> {
+    let input = input.into_value(call.head)?;
+    nuon_to_bam(call, &input).map(|v| PipelineData::Value(v, None))
 }
 
 /// Parse a CRAM file.
-pub fn from_cram(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    from_cram_inner(call, input)
+pub fn from_cram(
+    call: &EvaluatedCall,
+    input: PipelineData,
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    let reference = match flag_path(call, engine, "reference")? {
+        Some(path) => Some(read_reference(call, &path)?),
+        None => None,
+    };
+
+    let input = input_to_reader(call, input)?;
+    header_and_body(call, from_cram_inner(call, input, reference)?, engine)
 }
 
 /// Parse a BCF.
 pub fn from_bcf(
     call: &EvaluatedCall,
-    input: &Value,
+    input: PipelineData,
     gz: &Compression,
-) -> Result<Value, LabeledError> {
-    from_bcf_inner(call, input, gz)
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    if let Some(regions) = regions_from(call)? {
+        let path = indexed_input_path(call, &input)?;
+        let index = flag_path(call, engine, "index")?;
+        return header_and_body(call, query_bcf_inner(call, &path, index, regions)?, engine);
+    }
+
+    let input = input_to_reader(call, input)?;
+    header_and_body(call, from_bcf_inner(call, input, gz)?, engine)
 }
 /// Parse a VCF.
 pub fn from_vcf(
     call: &EvaluatedCall,
-    input: &Value,
+    input: PipelineData,
     gz: &Compression,
-) -> Result<Value, LabeledError> {
-    from_vcf_inner(call, input, gz)
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    if let Some(regions) = regions_from(call)? {
+        let path = indexed_input_path(call, &input)?;
+        let index = flag_path(call, engine, "index")?;
+        return header_and_body(call, query_vcf_inner(call, &path, index, regions)?, engine);
+    }
+
+    let input = input_to_reader(call, input)?;
+    header_and_body(call, from_vcf_inner(call, input, gz)?, engine)
 }
 
-/// Parse a GFF.
-pub fn from_gff(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    let value_records = from_gff_inner(call, input)?;
-    Ok(Value::list(value_records, call.head))
+/// Structured variants to VCF, optionally BGZF compressed.
+pub fn to_vcf(
+    call: &EvaluatedCall,
+    input: PipelineData,
+    gz: &Compression,
+) -> Result<PipelineData, LabeledError> {
+    let input = input.into_value(call.head)?;
+    nuon_to_vcf(call, &input, gz).map(|v| PipelineData::Value(v, None))
+}
+
+/// Structured variants to BCF.
+pub fn to_bcf(call: &EvaluatedCall, input: PipelineData) -> Result<PipelineData, LabeledError> {
+    let input = input.into_value(call.head)?;
+    nuon_to_bcf(call, &input).map(|v| PipelineData::Value(v, None))
+}
+
+/// Stream the features of a GFF, or parse it into its directives, features and sequences
+/// with `--header`.
+pub fn from_gff(
+    call: &EvaluatedCall,
+    input: PipelineData,
+    gz: &Compression,
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    let input = input_to_reader(call, input)?;
+    if call.has_flag("header")? {
+        return from_gff_inner(call, input, gz).map(|v| PipelineData::Value(v, None));
+    }
+
+    let features = stream_gff_inner(call, input, gz)?;
+    Ok(list_stream(features, call, engine.signals()))
+}
+
+/// Parse a GTF.
+pub fn from_gtf(
+    call: &EvaluatedCall,
+    input: PipelineData,
+    gz: &Compression,
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    let input = input_to_reader(call, input)?;
+    let value_records = from_gtf_inner(call, input, gz)?;
+    Ok(list_stream(value_records, call, engine.signals()))
 }
 
 /// Parse a GFA.
 pub fn from_gfa(
     call: &EvaluatedCall,
-    input: &Value,
+    input: PipelineData,
     gz: &Compression,
-) -> Result<Value, LabeledError> {
-    from_gfa_inner(call, input, gz)
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    let input = input_to_reader(call, input)?;
+    let value_records = from_gfa_inner(call, input, gz)?;
+    Ok(list_stream(value_records, call, engine.signals()))
 }
 
 /// Parse a BED.
-pub fn from_bed(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    from_bed_inner(call, input).map(|e| Value::list(e, call.head))
+pub fn from_bed(
+    call: &EvaluatedCall,
+    input: PipelineData,
+    gz: &Compression,
+    engine: &EngineInterface,
+) -> Result<PipelineData, LabeledError> {
+    let input = input_to_reader(call, input)?;
+    from_bed_inner(call, input, gz).map(|e| list_stream(e, call, engine.signals()))
 }
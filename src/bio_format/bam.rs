@@ -1,20 +1,59 @@
-use crate::bio_format::SpanExt;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Read};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::bio_format::index::{query_chunks, read_index, IndexedReader, Query};
+use crate::bio_format::{
+    float_to_nuon, int_value, optional, parse_value, required, stream_records, Input, SpanExt,
+    ValueStream,
+};
 use noodles::{
     bam,
-    sam::{self, alignment::Record as SAMRecord, header::record::value::Map},
+    bgzf::{self, VirtualPosition},
+    core::{Position, Region},
+    sam::{
+        self,
+        alignment::Record as SAMRecord,
+        header::record::value::{
+            map::{Header as HeaderMap, Program, ReadGroup, ReferenceSequence},
+            Map,
+        },
+        record::{
+            cigar::{op::Kind, Op},
+            data::field::{
+                tag,
+                value::{Array, Character},
+                Tag, Value as DataValue,
+            },
+            Cigar, Data, Flags, MappingQuality,
+        },
+        AlignmentWriter,
+    },
 };
 use nu_plugin::EvaluatedCall;
-use nu_protocol::{record, LabeledError, Record, Value};
+use nu_protocol::{record, LabeledError, Record, Span, Value};
 
 /// Columns in a BAM/SAM file
 pub const BAM_COLUMNS: &[&str] = &[
     "read_name",
     "flags",
-    "reference_sequence_id",
+    "flag",
+    "reference_sequence",
     "alignment_start",
     "mapping_quality",
     "cigar",
-    "mate_reference_sequence_id",
+    "alignment_end",
+    "reference_length",
+    "query_length",
+    "soft_clip_start",
+    "soft_clip_end",
+    "hard_clip_start",
+    "hard_clip_end",
+    "percent_identity",
+    "mate_reference_sequence",
     "mate_alignment_start",
     "template_length",
     "sequence",
@@ -47,7 +86,7 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
         // if it's no good, we can always map -> string
         "sorting_order" => call.head.with_string(header.sort_order().unwrap_or_default()),
         "grouping" => call.head.with_string(header.group_order().unwrap_or_default()),
-        "sub_sort_order" => call.head.with_string_or(header.subsort_order(), "No subsort order.")
+        "sub_sort_order" => call.head.with_string_or_null(header.subsort_order())
         ),
         call.head,
     );
@@ -59,14 +98,14 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
     .map(|(name, f)| Value::record(record! {
             "sequence_name" => call.head.with_string(name),
             "sequence_length" => Value::int(usize::from(f.length()) as i64, call.head),
-            "alternate_locus" => call.head.with_string_or(f.alternative_locus(), "No alternative locus."),
-            "alternate_names" => call.head.with_string_or(f.alternative_names(), "No alternative names."),
-            "assembly_id" => call.head.with_string_or(f.assembly_id(), "No assembly ID."),
-            "description" => call.head.with_string_or(f.description(), "No description"),
-            "md5" => call.head.with_string_or(f.md5_checksum(), "No md5 checksum"),
-            "species" => call.head.with_string_or(f.species(), "No species name"),
-            "molecule_topology" => call.head.with_string_or(f.molecule_topology(), "No molecule topology"),
-            "uri" => call.head.with_string_or(f.uri(), "No URI"),
+            "alternate_locus" => call.head.with_string_or_null(f.alternative_locus()),
+            "alternate_names" => call.head.with_string_or_null(f.alternative_names()),
+            "assembly_id" => call.head.with_string_or_null(f.assembly_id()),
+            "description" => call.head.with_string_or_null(f.description()),
+            "md5" => call.head.with_string_or_null(f.md5_checksum()),
+            "species" => call.head.with_string_or_null(f.species()),
+            "molecule_topology" => call.head.with_string_or_null(f.molecule_topology()),
+            "uri" => call.head.with_string_or_null(f.uri()),
         }, call.head)
     )
     .collect(), call.head, call.head).unwrap(),
@@ -79,25 +118,25 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
     .iter()
     .map(|(id, f)| Value::record(record! {
             "id" => call.head.with_string(id),
-            "barcode" => call.head.with_string_or(f.barcode(), "No barcode"),
-            "sequencing_center" => call.head.with_string_or(f.sequencing_center(), "No sequencing center"),
-            "description" => call.head.with_string_or(f.description(), "No description"),
+            "barcode" => call.head.with_string_or_null(f.barcode()),
+            "sequencing_center" => call.head.with_string_or_null(f.sequencing_center()),
+            "description" => call.head.with_string_or_null(f.description()),
             // no date?
             // "date",
-            "flow_order" => call.head.with_string_or(f.flow_order(), "No flow order"),
-            "key_sequence" => call.head.with_string_or(f.key_sequence(), "No key sequence"),
-            "library" => call.head.with_string_or(f.library(), "No library"),
-            "program" => call.head.with_string_or(f.program(), "No program"),
-            "platform" => call.head.with_string_or(f.platform(), "No platform"),
+            "flow_order" => call.head.with_string_or_null(f.flow_order()),
+            "key_sequence" => call.head.with_string_or_null(f.key_sequence()),
+            "library" => call.head.with_string_or_null(f.library()),
+            "program" => call.head.with_string_or_null(f.program()),
+            "platform" => call.head.with_string_or_null(f.platform()),
             "predicted_insert_size" => Value::int(
                  f.predicted_median_insert_size()
                         .map(|e| e as i64)
                         .unwrap_or(0),
             call.head
         ), // CAPILLARY, DNBSEQ (MGI/BGI), ELEMENT, HELICOS, ILLUMINA, IONTORRENT, LS454, ONT (Oxford Nanopore), PACBIO (Pacific Biosciences), SOLID, and ULTIMA
-            "platform_model" => call.head.with_string_or(f.platform_model(), "No platform model"),
-            "platform_unit" => call.head.with_string_or(f.platform_unit(), "No platform unit"),
-            "sample" => call.head.with_string_or(f.sample(), "No sample"),
+            "platform_model" => call.head.with_string_or_null(f.platform_model()),
+            "platform_unit" => call.head.with_string_or_null(f.platform_unit()),
+            "sample" => call.head.with_string_or_null(f.sample()),
         }, call.head
     ))
     .collect(), call.head, call.head).unwrap(),
@@ -107,22 +146,30 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
     // @PG
     let programs = h.programs();
 
-    let programs_nuon = Value::record(Record::from_raw_cols_vals(
-        programs.keys().cloned().collect(),
-        programs
-            .iter()
-            .map(|(id, f)| Value::record(record! {
-                    "id" => call.head.with_string(id),
-                    "name" => call.head.with_string_or(f.name(), "No name"),
-                    "command_line" => call.head.with_string_or(f.command_line(), "No command line"),
-                    "previous_id" => call.head.with_string_or(f.previous_id(), "No previous ID"),
-                    "description" => call.head.with_string_or(f.description(), "No description"),
-                    "version" => call.head.with_string_or(f.version(), "No version"),
-                }, call.head)
-            ).collect(),
+    let programs_nuon = Value::record(
+        Record::from_raw_cols_vals(
+            programs.keys().cloned().collect(),
+            programs
+                .iter()
+                .map(|(id, f)| {
+                    Value::record(
+                        record! {
+                            "id" => call.head.with_string(id),
+                            "name" => call.head.with_string_or_null(f.name()),
+                            "command_line" => call.head.with_string_or_null(f.command_line()),
+                            "previous_id" => call.head.with_string_or_null(f.previous_id()),
+                            "description" => call.head.with_string_or_null(f.description()),
+                            "version" => call.head.with_string_or_null(f.version()),
+                        },
+                        call.head,
+                    )
+                })
+                .collect(),
+            call.head,
+            call.head,
+        )
+        .unwrap(),
         call.head,
-        call.head
-    ).unwrap(), call.head
     );
 
     // @CO
@@ -144,52 +191,310 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
     )
 }
 
-/// Parse a SAM record, and append to a vector
-pub fn create_record_values(call: &EvaluatedCall, r: SAMRecord) -> Vec<Value> {
-    let flags = r.flags().bits();
-    let mapping_quality = r
-        .mapping_quality()
-        .map(|m_q| format!("{}", u8::from(m_q)))
-        .unwrap_or_default();
-    let sequence: Vec<u8> = r.sequence().as_ref().iter().map(|e| u8::from(*e)).collect();
+/// The bits of the SAM flags as a record of named booleans, so they can be filtered on.
+fn flags_to_nuon(flags: Flags, span: Span) -> Value {
+    Value::record(
+        record! {
+            "paired" => Value::bool(flags.is_segmented(), span),
+            "proper_pair" => Value::bool(flags.is_properly_aligned(), span),
+            "unmapped" => Value::bool(flags.is_unmapped(), span),
+            "mate_unmapped" => Value::bool(flags.is_mate_unmapped(), span),
+            "reverse" => Value::bool(flags.is_reverse_complemented(), span),
+            "mate_reverse" => Value::bool(flags.is_mate_reverse_complemented(), span),
+            "first" => Value::bool(flags.is_first_segment(), span),
+            "last" => Value::bool(flags.is_last_segment(), span),
+            "secondary" => Value::bool(flags.is_secondary(), span),
+            "qc_fail" => Value::bool(flags.is_qc_fail(), span),
+            "duplicate" => Value::bool(flags.is_duplicate(), span),
+            "supplementary" => Value::bool(flags.is_supplementary(), span),
+        },
+        span,
+    )
+}
 
-    vec![
-        call.head.with_string_or(r.read_name(), "No read name."),
-        call.head.with_string(format!("{:#06x}", flags)),
-        call.head
-            .with_string_or(r.reference_sequence_id(), "No reference sequence ID"),
-        call.head
-            .with_string_or(r.alignment_start(), "No alignment start"),
-        call.head.with_string(mapping_quality),
-        call.head.with_string(r.cigar()),
-        call.head.with_string_or(
-            r.mate_reference_sequence_id(),
-            "No mate reference sequence ID",
+/// A position as an integer, or `null` if it's missing.
+fn position_to_nuon(position: Option<Position>, span: Span) -> Value {
+    position.map_or(Value::nothing(span), |p| {
+        Value::int(usize::from(p) as i64, span)
+    })
+}
+
+/// The name of a reference sequence a record refers to, or `null` if it has none.
+fn reference_sequence_to_nuon<N: ToString, M>(
+    reference_sequence: Option<io::Result<(N, M)>>,
+    span: Span,
+) -> Result<Value, LabeledError> {
+    match reference_sequence.transpose() {
+        Ok(Some((name, _))) => Ok(span.with_string(name)),
+        Ok(None) => Ok(Value::nothing(span)),
+        Err(e) => Err(
+            LabeledError::new(format!("cause of failure: {}", e)).with_label(
+                "Record refers to a reference sequence not in the header.",
+                span,
+            ),
         ),
-        call.head
-            .with_string_or(r.mate_alignment_start(), "No mate alignment start"),
-        call.head.with_string(r.template_length()),
-        call.head.with_string(String::from_utf8(sequence).unwrap()),
-        call.head.with_string(r.quality_scores()),
-        call.head.with_string(r.data()),
-    ]
+    }
 }
 
-/// Parse a BAM file into a nushell structure.
-pub fn from_bam_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val,
-        other => {
-            return Err(LabeledError::new(format!(
-                "requires binary input, got {}",
-                other.get_type()
-            ))
-            .with_label("Input should be binary.", call.head))
+/// An auxiliary data value, typed by its SAM type code.
+fn data_value_to_nuon(value: &DataValue, span: Span) -> Value {
+    fn ints<T: Copy + Into<i64>>(vals: &[T], span: Span) -> Value {
+        Value::list(
+            vals.iter().map(|&v| Value::int(v.into(), span)).collect(),
+            span,
+        )
+    }
+
+    match value {
+        DataValue::Character(c) => span.with_string(c),
+        DataValue::Int8(_)
+        | DataValue::UInt8(_)
+        | DataValue::Int16(_)
+        | DataValue::UInt16(_)
+        | DataValue::Int32(_)
+        | DataValue::UInt32(_) => Value::int(value.as_int().unwrap_or_default(), span),
+        DataValue::Float(f) => float_to_nuon(*f, span),
+        DataValue::String(s) => span.with_string(s),
+        DataValue::Hex(h) => span.with_string(h),
+        DataValue::Array(Array::Int8(vals)) => ints(vals, span),
+        DataValue::Array(Array::UInt8(vals)) => ints(vals, span),
+        DataValue::Array(Array::Int16(vals)) => ints(vals, span),
+        DataValue::Array(Array::UInt16(vals)) => ints(vals, span),
+        DataValue::Array(Array::Int32(vals)) => ints(vals, span),
+        DataValue::Array(Array::UInt32(vals)) => ints(vals, span),
+        DataValue::Array(Array::Float(vals)) => {
+            Value::list(vals.iter().map(|&v| float_to_nuon(v, span)).collect(), span)
         }
+    }
+}
+
+/// The auxiliary data of a record, keyed by tag.
+fn data_to_nuon(data: &Data, span: Span) -> Value {
+    Value::record(
+        data.iter()
+            .map(|(tag, value)| (tag.to_string(), data_value_to_nuon(value, span)))
+            .collect(),
+        span,
+    )
+}
+
+/// The CIGAR as a list of `{op, len}` records.
+fn cigar_to_nuon(cigar: &Cigar, span: Span) -> Value {
+    Value::list(
+        cigar
+            .iter()
+            .map(|op| {
+                Value::record(
+                    record! {
+                        "op" => span.with_string(char::from(op.kind())),
+                        "len" => Value::int(op.len() as i64, span),
+                    },
+                    span,
+                )
+            })
+            .collect(),
+        span,
+    )
+}
+
+/// The lengths of the soft and hard clips at one end of an alignment, given the ops
+/// from that end inwards.
+fn clips<'a>(ops: impl Iterator<Item = &'a Op>) -> (usize, usize) {
+    ops.take_while(|op| matches!(op.kind(), Kind::SoftClip | Kind::HardClip))
+        .fold((0, 0), |(soft, hard), op| match op.kind() {
+            Kind::SoftClip => (soft + op.len(), hard),
+            _ => (soft, hard + op.len()),
+        })
+}
+
+/// Percent identity over the alignment columns (matches, insertions and deletions),
+/// counting each edit as a difference.
+fn percent_identity(cigar: &Cigar, edit_distance: i64) -> Option<f64> {
+    let columns: usize = cigar
+        .iter()
+        .filter(|op| {
+            matches!(
+                op.kind(),
+                Kind::Match
+                    | Kind::SequenceMatch
+                    | Kind::SequenceMismatch
+                    | Kind::Insertion
+                    | Kind::Deletion
+            )
+        })
+        .map(|op| op.len())
+        .sum();
+
+    (columns > 0).then(|| 100.0 * (1.0 - edit_distance as f64 / columns as f64))
+}
+
+/// The number of mismatched bases in an `MD` tag, e.g. 2 in `10A5^AC6T0`, where the
+/// bases after `^` are deleted from the reference rather than mismatched.
+fn md_mismatches(md: &str) -> i64 {
+    let mut deletion = false;
+    let mut mismatches = 0;
+    for c in md.chars() {
+        match c {
+            '^' => deletion = true,
+            c if c.is_ascii_digit() => deletion = false,
+            _ if !deletion => mismatches += 1,
+            _ => {}
+        }
+    }
+    mismatches
+}
+
+/// The edit distance of an alignment, from `NM`, or if that's missing, the mismatches in
+/// `MD` plus the inserted and deleted bases in the CIGAR.
+fn edit_distance(r: &SAMRecord) -> Option<i64> {
+    let data = r.data();
+    if let Some(nm) = data.get(&tag::EDIT_DISTANCE).and_then(DataValue::as_int) {
+        return Some(nm);
+    }
+
+    let md = data.get(&tag::MISMATCHED_POSITIONS)?.as_str()?;
+    let indels: usize = r
+        .cigar()
+        .iter()
+        .filter(|op| matches!(op.kind(), Kind::Insertion | Kind::Deletion))
+        .map(|op| op.len())
+        .sum();
+    Some(md_mismatches(md) + indels as i64)
+}
+
+/// Columns derived from the CIGAR: where the alignment ends, how much of the reference
+/// and the read it covers, the clips at each end, and percent identity if `NM` or `MD` is
+/// present.
+fn cigar_stats(r: &SAMRecord, span: Span) -> Vec<Value> {
+    let cigar = r.cigar();
+    let int = |n: usize| Value::int(n as i64, span);
+
+    let query_length = cigar
+        .iter()
+        .filter(|op| op.kind().consumes_read() && op.kind() != Kind::SoftClip)
+        .map(|op| op.len())
+        .sum();
+    let (soft_clip_start, hard_clip_start) = clips(cigar.iter());
+    let (soft_clip_end, hard_clip_end) = clips(cigar.iter().rev());
+
+    let identity = edit_distance(r).and_then(|nm| percent_identity(cigar, nm));
+
+    vec![
+        position_to_nuon(r.alignment_end(), span),
+        int(cigar.alignment_span()),
+        int(query_length),
+        int(soft_clip_start),
+        int(soft_clip_end),
+        int(hard_clip_start),
+        int(hard_clip_end),
+        identity.map_or(Value::nothing(span), |i| Value::float(i, span)),
+    ]
+}
+
+/// Parse the tags passed to `--tags`, either comma separated (e.g. `NM,AS`) or as a list.
+pub fn tags_from(call: &EvaluatedCall) -> Result<Vec<Tag>, LabeledError> {
+    let Some(value) = call.get_flag_value("tags") else {
+        return Ok(Vec::new());
+    };
+
+    let tags = match &value {
+        Value::List { vals, .. } => vals.iter().map(Value::as_str).collect::<Result<_, _>>()?,
+        other => other.as_str()?.split(',').collect::<Vec<_>>(),
     };
 
-    let mut reader = bam::Reader::new(stream.as_slice());
+    tags.into_iter()
+        .map(|tag| {
+            tag.trim().parse().map_err(|e| {
+                LabeledError::new(format!("invalid tag {tag}: {e}"))
+                    .with_label("Could not parse tag.", value.span())
+            })
+        })
+        .collect()
+}
+
+/// Parse a SAM record into a row of values.
+///
+/// Reference sequence IDs are resolved to their names with the header.
+fn create_record_values(
+    span: Span,
+    header: &sam::Header,
+    r: &SAMRecord,
+) -> Result<Vec<Value>, LabeledError> {
+    let flags = r.flags();
+    let sequence: Vec<u8> = r.sequence().as_ref().iter().map(|e| u8::from(*e)).collect();
+
+    let mut values = vec![
+        r.read_name()
+            .map_or(Value::nothing(span), |name| span.with_string(name)),
+        Value::int(i64::from(flags.bits()), span),
+        flags_to_nuon(flags, span),
+        reference_sequence_to_nuon(r.reference_sequence(header), span)?,
+        position_to_nuon(r.alignment_start(), span),
+        r.mapping_quality().map_or(Value::nothing(span), |m_q| {
+            Value::int(i64::from(u8::from(m_q)), span)
+        }),
+        cigar_to_nuon(r.cigar(), span),
+    ];
+    values.extend(cigar_stats(r, span));
+    values.extend([
+        reference_sequence_to_nuon(r.mate_reference_sequence(header), span)?,
+        position_to_nuon(r.mate_alignment_start(), span),
+        Value::int(i64::from(r.template_length()), span),
+        span.with_string(String::from_utf8(sequence).unwrap()),
+        span.with_string(r.quality_scores()),
+        data_to_nuon(r.data(), span),
+    ]);
+
+    Ok(values)
+}
+
+/// Parse a SAM record into a nushell record.
+///
+/// Any `tags` asked for are added as columns of their own, `null` if the record
+/// doesn't have them.
+pub fn alignment_to_nuon(
+    span: Span,
+    header: &sam::Header,
+    tags: &[Tag],
+    r: SAMRecord,
+) -> Result<Value, LabeledError> {
+    let mut record = Record::from_iter(
+        BAM_COLUMNS
+            .iter()
+            .map(|e| e.to_string())
+            .zip(create_record_values(span, header, &r)?),
+    );
+
+    for tag in tags {
+        let value = r.data().get(tag).map_or(Value::nothing(span), |value| {
+            data_value_to_nuon(value, span)
+        });
+        record.push(tag.to_string(), value);
+    }
+
+    Ok(Value::record(record, span))
+}
+
+/// Turn a SAM record (or the error reading it) into a nushell record.
+pub fn record_to_nuon(
+    record: io::Result<SAMRecord>,
+    header: &sam::Header,
+    tags: &[Tag],
+    span: Span,
+) -> Result<Value, LabeledError> {
+    let r = record.map_err(|e| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label("Record reading failed.", span)
+    })?;
+
+    alignment_to_nuon(span, header, tags, r)
+}
+
+/// Read the header of a BAM, returning it along with its nuon representation.
+fn read_bam_header<R: Read>(
+    reader: &mut bam::Reader<bgzf::Reader<R>>,
+    call: &EvaluatedCall,
+) -> Result<(sam::Header, Value), LabeledError> {
     let raw_header = reader.read_header().map_err(|err| {
         LabeledError::new(format!("error reading header at {}", err))
             .with_label("Could not read header.", call.head)
@@ -210,70 +515,586 @@ pub fn from_bam_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, Labe
         parse_header(call, &raw_header)
     };
 
-    let value_records = reader
-        .records(&raw_header)
-        .map(|record| {
-            let r = record.map_err(|e| {
-                LabeledError::new(format!("cause of failure: {}", e))
-                    .with_label("Record reading failed.", call.head)
-            })?;
+    Ok((raw_header, header))
+}
 
-            let inner_record = Record::from_iter(
-                BAM_COLUMNS
-                    .iter()
-                    .map(|e| e.to_string())
-                    .zip(create_record_values(call, r)),
-            );
+/// Parse a BAM file into its header and a stream of records.
+pub fn from_bam_inner(
+    call: &EvaluatedCall,
+    input: Input,
+) -> Result<(Value, ValueStream), LabeledError> {
+    let mut reader = bam::Reader::new(input);
+    let (raw_header, header) = read_bam_header(&mut reader, call)?;
+    let tags = tags_from(call)?;
 
-            Ok(Value::record(inner_record, call.head))
-        })
-        .collect::<Result<Vec<_>, LabeledError>>()?;
+    let span = call.head;
+    let records = std::iter::from_fn(move || {
+        let record = reader.records(&raw_header).next()?;
+        Some(record_to_nuon(record, &raw_header, &tags, span))
+    });
 
-    Ok(Value::record(
-        record! {
-            "header" => header,
-            "body" => Value::list(value_records, call.head)
-        },
-        call.head,
-    ))
+    Ok((header, stream_records(records, span)))
+}
+
+/// An indexed BAM, along with the header needed to decode its records.
+struct IndexedBam {
+    reader: bam::Reader<bgzf::Reader<File>>,
+    header: sam::Header,
 }
 
-/// Parse a SAM file into a nushell structure.
-pub fn from_sam_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    // match on file type
-    // TODO: remove this unwrap
-    let stream = input.as_binary().unwrap();
+impl IndexedReader for IndexedBam {
+    type Record = SAMRecord;
+
+    fn seek(&mut self, pos: VirtualPosition) -> io::Result<VirtualPosition> {
+        self.reader.seek(pos)
+    }
+
+    fn virtual_position(&self) -> VirtualPosition {
+        self.reader.virtual_position()
+    }
+
+    fn read_next(&mut self) -> Option<io::Result<SAMRecord>> {
+        self.reader.records(&self.header).next()
+    }
+
+    fn intersects(
+        &self,
+        record: &SAMRecord,
+        reference_sequence_id: usize,
+        region: &Region,
+    ) -> io::Result<bool> {
+        Ok(
+            match (
+                record.reference_sequence_id(),
+                record.alignment_start(),
+                record.alignment_end(),
+            ) {
+                (Some(id), Some(start), Some(end)) => {
+                    id == reference_sequence_id
+                        && region.interval().intersects((start..=end).into())
+                }
+                _ => false,
+            },
+        )
+    }
+}
+
+/// Query regions of an indexed BAM file, returning its header and a stream of the
+/// records overlapping any of the regions.
+pub fn query_bam_inner(
+    call: &EvaluatedCall,
+    path: &Path,
+    index: Option<PathBuf>,
+    regions: Vec<Region>,
+) -> Result<(Value, ValueStream), LabeledError> {
+    let file = File::open(path).map_err(|e| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label(format!("Could not open {}.", path.display()), call.head)
+    })?;
+    let mut reader = bam::Reader::new(file);
+    let (raw_header, header) = read_bam_header(&mut reader, call)?;
+    let index = read_index(call, path, index, &["bai", "csi"])?;
 
-    let mut reader = sam::Reader::new(stream);
+    let reference_sequences = raw_header.reference_sequences();
+    let chunks = query_chunks(call, &index, regions, |name| {
+        reference_sequences.get_index_of(name)
+    })?;
+    let tags = tags_from(call)?;
+
+    let span = call.head;
+    let indexed = IndexedBam {
+        reader,
+        header: raw_header.clone(),
+    };
+    let records = Query::new(indexed, chunks)
+        .map(move |record| record_to_nuon(record, &raw_header, &tags, span));
+
+    Ok((header, stream_records(records, span)))
+}
+
+/// Parse a SAM file into its header and a stream of records.
+pub fn from_sam_inner(
+    call: &EvaluatedCall,
+    input: Input,
+) -> Result<(Value, ValueStream), LabeledError> {
+    let mut reader = sam::Reader::new(input);
     let header = reader.read_header().map_err(|err| {
         LabeledError::new(format!("{}", err)).with_label("Unable to parse SAM header", call.head)
     })?;
     let header_nuon = parse_header(call, &header);
+    let tags = tags_from(call)?;
+
+    let span = call.head;
+    let records = std::iter::from_fn(move || {
+        let record = reader.records(&header).next()?;
+        Some(record_to_nuon(record, &header, &tags, span))
+    });
+
+    Ok((header_nuon, stream_records(records, span)))
+}
+
+/// A header field written by [`parse_header`], `None` if it's missing or null.
+fn header_field<T>(row: &Record, column: &str) -> Result<Option<T>, LabeledError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    optional(row, column)
+        .map(|value| parse_value(value, column))
+        .transpose()
+}
+
+/// The `@HD` line.
+fn nuon_to_header_map(row: &Record) -> Result<Map<HeaderMap>, LabeledError> {
+    let mut builder = Map::<HeaderMap>::builder();
+
+    if let Some(version) = header_field(row, "version")? {
+        builder = builder.set_version(version);
+    }
+    if let Some(sort_order) = header_field(row, "sorting_order")? {
+        builder = builder.set_sort_order(sort_order);
+    }
+    if let Some(group_order) = header_field(row, "grouping")? {
+        builder = builder.set_group_order(group_order);
+    }
+    if let Some(subsort_order) = header_field(row, "sub_sort_order")? {
+        builder = builder.set_subsort_order(subsort_order);
+    }
+
+    Ok(builder.build().unwrap_or_default())
+}
+
+/// An `@SQ` line.
+fn nuon_to_reference_sequence(
+    row: &Record,
+    span: Span,
+) -> Result<Map<ReferenceSequence>, LabeledError> {
+    let length = required(row, "sequence_length", span)?;
+    let length = NonZeroUsize::new(int_value(length, "sequence_length")?).ok_or_else(|| {
+        LabeledError::new("sequence_length must be greater than 0")
+            .with_label("Invalid reference sequence length.", length.span())
+    })?;
+    let mut builder = Map::<ReferenceSequence>::builder().set_length(length);
+
+    if let Some(locus) = header_field(row, "alternate_locus")? {
+        builder = builder.set_alternative_locus(locus);
+    }
+    if let Some(names) = header_field(row, "alternate_names")? {
+        builder = builder.set_alternative_names(names);
+    }
+    if let Some(id) = header_field::<String>(row, "assembly_id")? {
+        builder = builder.set_assembly_id(id);
+    }
+    if let Some(description) = header_field::<String>(row, "description")? {
+        builder = builder.set_description(description);
+    }
+    if let Some(md5) = header_field(row, "md5")? {
+        builder = builder.set_md5_checksum(md5);
+    }
+    if let Some(species) = header_field::<String>(row, "species")? {
+        builder = builder.set_species(species);
+    }
+    if let Some(topology) = header_field(row, "molecule_topology")? {
+        builder = builder.set_molecule_topology(topology);
+    }
+    if let Some(uri) = header_field::<String>(row, "uri")? {
+        builder = builder.set_uri(uri);
+    }
+
+    builder.build().map_err(|e| {
+        LabeledError::new(format!("cause of failure: {e}"))
+            .with_label("Could not build reference sequence.", span)
+    })
+}
+
+/// An `@RG` line.
+fn nuon_to_read_group(row: &Record, span: Span) -> Result<Map<ReadGroup>, LabeledError> {
+    let mut builder = Map::<ReadGroup>::builder();
+
+    if let Some(barcode) = header_field::<String>(row, "barcode")? {
+        builder = builder.set_barcode(barcode);
+    }
+    if let Some(center) = header_field::<String>(row, "sequencing_center")? {
+        builder = builder.set_sequencing_center(center);
+    }
+    if let Some(description) = header_field::<String>(row, "description")? {
+        builder = builder.set_description(description);
+    }
+    if let Some(flow_order) = header_field::<String>(row, "flow_order")? {
+        builder = builder.set_flow_order(flow_order);
+    }
+    if let Some(key_sequence) = header_field::<String>(row, "key_sequence")? {
+        builder = builder.set_key_sequence(key_sequence);
+    }
+    if let Some(library) = header_field::<String>(row, "library")? {
+        builder = builder.set_library(library);
+    }
+    if let Some(program) = header_field::<String>(row, "program")? {
+        builder = builder.set_program(program);
+    }
+    if let Some(platform) = header_field(row, "platform")? {
+        builder = builder.set_platform(platform);
+    }
+    // a missing insert size is written as 0.
+    if let Some(size) = optional(row, "predicted_insert_size") {
+        let size = int_value(size, "predicted_insert_size")?;
+        if size != 0 {
+            builder = builder.set_predicted_median_insert_size(size);
+        }
+    }
+    if let Some(model) = header_field::<String>(row, "platform_model")? {
+        builder = builder.set_platform_model(model);
+    }
+    if let Some(unit) = header_field::<String>(row, "platform_unit")? {
+        builder = builder.set_platform_unit(unit);
+    }
+    if let Some(sample) = header_field::<String>(row, "sample")? {
+        builder = builder.set_sample(sample);
+    }
 
-    let value_records = reader
-        .records(&header)
-        .map(|record| {
-            let r = record.map_err(|e| {
-                LabeledError::new(format!("cause of failure: {}", e))
-                    .with_label("Record reading failed.", call.head)
+    builder.build().map_err(|e| {
+        LabeledError::new(format!("cause of failure: {e}"))
+            .with_label("Could not build read group.", span)
+    })
+}
+
+/// A `@PG` line.
+fn nuon_to_program(row: &Record, span: Span) -> Result<Map<Program>, LabeledError> {
+    let mut builder = Map::<Program>::builder();
+
+    if let Some(name) = header_field::<String>(row, "name")? {
+        builder = builder.set_name(name);
+    }
+    if let Some(command_line) = header_field::<String>(row, "command_line")? {
+        builder = builder.set_command_line(command_line);
+    }
+    if let Some(previous_id) = header_field::<String>(row, "previous_id")? {
+        builder = builder.set_previous_id(previous_id);
+    }
+    if let Some(description) = header_field::<String>(row, "description")? {
+        builder = builder.set_description(description);
+    }
+    if let Some(version) = header_field::<String>(row, "version")? {
+        builder = builder.set_version(version);
+    }
+
+    builder.build().map_err(|e| {
+        LabeledError::new(format!("cause of failure: {e}"))
+            .with_label("Could not build program.", span)
+    })
+}
+
+/// Build a SAM header from the header record returned by [`parse_header`].
+fn nuon_to_header(header: &Value) -> Result<sam::Header, LabeledError> {
+    let span = header.span();
+    let header = header.as_record()?;
+    let mut builder = sam::Header::builder();
+
+    if let Some(metadata) = optional(header, HEADER_COLUMNS[0]) {
+        builder = builder.set_header(nuon_to_header_map(metadata.as_record()?)?);
+    }
+    if let Some(reference_sequences) = optional(header, HEADER_COLUMNS[1]) {
+        for (name, reference_sequence) in reference_sequences.as_record()? {
+            let name = name.parse().map_err(|e| {
+                LabeledError::new(format!("invalid reference sequence name {name}: {e}"))
+                    .with_label("Could not parse name.", reference_sequence.span())
             })?;
+            let map = nuon_to_reference_sequence(reference_sequence.as_record()?, span)?;
+            builder = builder.add_reference_sequence(name, map);
+        }
+    }
+    if let Some(read_groups) = optional(header, HEADER_COLUMNS[2]) {
+        for (id, read_group) in read_groups.as_record()? {
+            builder =
+                builder.add_read_group(id, nuon_to_read_group(read_group.as_record()?, span)?);
+        }
+    }
+    if let Some(programs) = optional(header, HEADER_COLUMNS[3]) {
+        for (id, program) in programs.as_record()? {
+            builder = builder.add_program(id, nuon_to_program(program.as_record()?, span)?);
+        }
+    }
+    if let Some(comments) = optional(header, HEADER_COLUMNS[4]) {
+        for comment in comments.as_list()? {
+            builder = builder.add_comment(comment.as_str()?);
+        }
+    }
+
+    Ok(builder.build())
+}
+
+/// The index of a reference sequence in the header, from its name.
+fn reference_sequence_id(
+    header: &sam::Header,
+    value: &Value,
+    column: &str,
+) -> Result<usize, LabeledError> {
+    let name = value.as_str()?;
+    header
+        .reference_sequences()
+        .get_index_of(name)
+        .ok_or_else(|| {
+            LabeledError::new(format!("{column} {name} is not in the header"))
+                .with_label("Unknown reference sequence.", value.span())
+        })
+}
+
+/// A CIGAR from its list of `{op, len}` records, or from a CIGAR string.
+fn nuon_to_cigar(value: &Value) -> Result<Cigar, LabeledError> {
+    let Value::List { vals, .. } = value else {
+        return parse_value(value, "cigar");
+    };
+
+    vals.iter()
+        .map(|op| {
+            let op = op.as_record()?;
+            let kind = required(op, "op", value.span())?;
+            let len = required(op, "len", value.span())?;
+            Ok(Op::new(
+                parse_value(kind, "cigar op")?,
+                int_value(len, "cigar op length")?,
+            ))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|ops| {
+            Cigar::try_from(ops).map_err(|e| {
+                LabeledError::new(format!("invalid cigar: {e}"))
+                    .with_label("Could not build CIGAR.", value.span())
+            })
+        })
+}
+
+/// An integer data value, as the smallest type which fits it, as samtools does.
+fn int_to_data_value(i: i64) -> Option<DataValue> {
+    if i >= 0 {
+        u8::try_from(i)
+            .map(DataValue::UInt8)
+            .or_else(|_| u16::try_from(i).map(DataValue::UInt16))
+            .or_else(|_| u32::try_from(i).map(DataValue::UInt32))
+            .ok()
+    } else {
+        i8::try_from(i)
+            .map(DataValue::Int8)
+            .or_else(|_| i16::try_from(i).map(DataValue::Int16))
+            .or_else(|_| i32::try_from(i).map(DataValue::Int32))
+            .ok()
+    }
+}
+
+/// An integer array data value, as the smallest type which fits all of it.
+fn ints_to_array(ints: Vec<i64>) -> Option<Array> {
+    fn all<T: TryFrom<i64>>(ints: &[i64]) -> Option<Vec<T>> {
+        ints.iter().map(|&i| T::try_from(i).ok()).collect()
+    }
+
+    if ints.iter().all(|&i| i >= 0) {
+        all(&ints)
+            .map(Array::UInt8)
+            .or_else(|| all(&ints).map(Array::UInt16))
+            .or_else(|| all(&ints).map(Array::UInt32))
+    } else {
+        all(&ints)
+            .map(Array::Int8)
+            .or_else(|| all(&ints).map(Array::Int16))
+            .or_else(|| all(&ints).map(Array::Int32))
+    }
+}
+
+/// Tags whose values are single characters (`A`): `TS` from the SAM spec, and minimap2's
+/// `tp` and `ts`.
+const CHARACTER_TAGS: &[&str] = &["TS", "tp", "ts"];
 
-            let inner_record = Record::from_iter(
-                BAM_COLUMNS
+/// An auxiliary data value from nushell.
+///
+/// The SAM type isn't kept when reading, so strings are written as strings (`Z`), apart from
+/// the tags in [`CHARACTER_TAGS`], which are written as characters (`A`). Hex arrays (`H`)
+/// are written as strings too.
+fn nuon_to_data_value(value: &Value, tag: &str) -> Result<DataValue, LabeledError> {
+    let invalid = || {
+        LabeledError::new(format!("tag {tag} can't be written as SAM data"))
+            .with_label("Invalid auxiliary data value.", value.span())
+    };
+
+    match value {
+        Value::Int { val, .. } => int_to_data_value(*val).ok_or_else(invalid),
+        Value::Float { val, .. } => Ok(DataValue::Float(*val as f32)),
+        Value::String { val, .. } if CHARACTER_TAGS.contains(&tag) => {
+            let mut chars = val.chars();
+            match (chars.next().map(Character::try_from), chars.next()) {
+                (Some(Ok(c)), None) => Ok(DataValue::Character(c)),
+                _ => Err(invalid()),
+            }
+        }
+        Value::String { val, .. } => Ok(DataValue::String(val.clone())),
+        Value::List { vals, .. } => {
+            // a float anywhere makes it a float array, e.g. `[1 0.5]`, so ints are coerced
+            if vals.iter().any(|v| matches!(v, Value::Float { .. })) {
+                let floats = vals
                     .iter()
-                    .map(|e| e.to_string())
-                    .zip(create_record_values(call, r)),
-            );
+                    .map(|v| match v {
+                        Value::Int { val, .. } => Ok(*val as f32),
+                        Value::Float { val, .. } => Ok(*val as f32),
+                        _ => Err(invalid()),
+                    })
+                    .collect::<Result<_, LabeledError>>()?;
+                Ok(DataValue::Array(Array::Float(floats)))
+            } else {
+                let ints = vals.iter().map(Value::as_int).collect::<Result<_, _>>()?;
+                ints_to_array(ints)
+                    .map(DataValue::Array)
+                    .ok_or_else(invalid)
+            }
+        }
+        _ => Err(invalid()),
+    }
+}
 
-            Ok(Value::record(inner_record, call.head))
+/// Auxiliary data from its record of tags.
+fn nuon_to_data(value: &Value) -> Result<Data, LabeledError> {
+    value
+        .as_record()?
+        .iter()
+        .map(|(tag, v)| {
+            let tag: Tag = tag.parse().map_err(|e| {
+                LabeledError::new(format!("invalid tag {tag}: {e}"))
+                    .with_label("Could not parse tag.", value.span())
+            })?;
+            Ok((tag, nuon_to_data_value(v, &tag.to_string())?))
         })
-        .collect::<Result<Vec<_>, LabeledError>>()?;
+        .collect()
+}
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head)
-        },
-        call.head,
-    ))
+/// Build a SAM record from a row of the body, as returned by [`alignment_to_nuon`].
+///
+/// The `flags` integer is used rather than the `flag` record, and columns derived from
+/// the CIGAR are ignored.
+fn nuon_to_alignment(header: &sam::Header, row: &Value) -> Result<SAMRecord, LabeledError> {
+    let span = row.span();
+    let row = row.as_record()?;
+    let mut builder = SAMRecord::builder();
+
+    if let Some(read_name) = optional(row, "read_name") {
+        builder = builder.set_read_name(parse_value(read_name, "read_name")?);
+    }
+    builder = builder.set_flags(Flags::from(int_value::<u16>(
+        required(row, "flags", span)?,
+        "flags",
+    )?));
+    if let Some(name) = optional(row, "reference_sequence") {
+        builder = builder.set_reference_sequence_id(reference_sequence_id(
+            header,
+            name,
+            "reference_sequence",
+        )?);
+    }
+    if let Some(start) = optional(row, "alignment_start") {
+        builder = builder.set_alignment_start(parse_position(start, "alignment_start")?);
+    }
+    if let Some(quality) = optional(row, "mapping_quality") {
+        // 255 is how a missing mapping quality is stored.
+        if let Some(quality) = MappingQuality::new(int_value(quality, "mapping_quality")?) {
+            builder = builder.set_mapping_quality(quality);
+        }
+    }
+    if let Some(cigar) = optional(row, "cigar") {
+        builder = builder.set_cigar(nuon_to_cigar(cigar)?);
+    }
+    if let Some(name) = optional(row, "mate_reference_sequence") {
+        builder = builder.set_mate_reference_sequence_id(reference_sequence_id(
+            header,
+            name,
+            "mate_reference_sequence",
+        )?);
+    }
+    if let Some(start) = optional(row, "mate_alignment_start") {
+        builder = builder.set_mate_alignment_start(parse_position(start, "mate_alignment_start")?);
+    }
+    if let Some(length) = optional(row, "template_length") {
+        builder = builder.set_template_length(int_value(length, "template_length")?);
+    }
+    if let Some(sequence) = optional(row, "sequence").filter(|v| !is_empty_string(v)) {
+        builder = builder.set_sequence(parse_value(sequence, "sequence")?);
+    }
+    if let Some(scores) = optional(row, "quality_scores").filter(|v| !is_empty_string(v)) {
+        builder = builder.set_quality_scores(parse_value(scores, "quality_scores")?);
+    }
+    if let Some(data) = optional(row, "data") {
+        builder = builder.set_data(nuon_to_data(data)?);
+    }
+
+    Ok(builder.build())
+}
+
+/// Empty strings are how missing sequences and quality scores are read.
+fn is_empty_string(value: &Value) -> bool {
+    matches!(value, Value::String { val, .. } if val.is_empty())
+}
+
+/// A 1-based position from an int.
+fn parse_position(value: &Value, column: &str) -> Result<Position, LabeledError> {
+    Position::new(int_value(value, column)?).ok_or_else(|| {
+        LabeledError::new(format!("{column} must be greater than 0"))
+            .with_label("Invalid position.", value.span())
+    })
+}
+
+/// Write the `{header, body}` record returned by `from sam`/`from bam` with a SAM or BAM writer.
+fn write_alignments(
+    call: &EvaluatedCall,
+    input: &Value,
+    writer: &mut dyn AlignmentWriter,
+) -> Result<(), LabeledError> {
+    let write_error = |e: io::Error| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label("Could not write alignments.", call.head)
+    };
+
+    let input = input.as_record().map_err(|_| {
+        LabeledError::new("expected a record of the header and the body")
+            .with_label("Input should be a record.", call.head)
+            .with_help("pass the output of `from sam --header` or `from bam --header`")
+    })?;
+    let header = nuon_to_header(required(input, "header", call.head)?)?;
+    let body = required(input, "body", call.head)?.as_list()?;
+
+    writer
+        .write_alignment_header(&header)
+        .map_err(write_error)?;
+
+    for (i, row) in body.iter().enumerate() {
+        let record = nuon_to_alignment(&header, row).map_err(|e| {
+            let msg = e.msg.clone();
+            e.with_label(format!("Could not write record {i}: {msg}."), call.head)
+        })?;
+        writer
+            .write_alignment_record(&header, &record)
+            .map_err(write_error)?;
+    }
+
+    writer.finish(&header).map_err(write_error)
+}
+
+/// Write alignments out as SAM text.
+pub fn nuon_to_sam(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let mut writer = sam::Writer::new(Vec::new());
+    write_alignments(call, input, &mut writer)?;
+
+    let out = String::from_utf8(writer.into_inner()).map_err(|err| {
+        LabeledError::new(err.to_string()).with_label("Can't format bytes as UTF-8", call.head)
+    })?;
+
+    Ok(Value::string(out, call.head))
+}
+
+/// Write alignments out as BGZF compressed BAM.
+pub fn nuon_to_bam(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let mut writer = bam::Writer::new(Vec::new());
+    write_alignments(call, input, &mut writer)?;
+
+    let out = writer.into_inner().finish().map_err(|e| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label("Could not finish the BAM.", call.head)
+    })?;
+
+    Ok(Value::binary(out, call.head))
 }
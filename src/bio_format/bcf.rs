@@ -1,26 +1,65 @@
 /// The VCF format
 use noodles::{
     bcf::{self, header::StringMaps},
-    bgzf, vcf,
+    bgzf,
+    vcf::{
+        self,
+        header::record::value::{
+            map::{
+                info::Type as InfoType, AlternativeAllele, Contig, Filter, Format, Info as InfoMap,
+                Other,
+            },
+            Collection, Map,
+        },
+        header::record::Value as HeaderValue,
+        header::Number,
+        record::{
+            genotypes::{
+                keys::key,
+                sample::{
+                    value::{genotype::allele::Phasing, Array as SampleArray, Genotype},
+                    Value as SampleValue,
+                },
+                Genotypes,
+            },
+            info::field::{value::Array as InfoArray, Value as InfoValue},
+            Info,
+        },
+        VariantWriter,
+    },
 };
 use nu_plugin::EvaluatedCall;
-use nu_protocol::{record, LabeledError, Record, Value};
+use nu_protocol::{record, LabeledError, Record, Span, Value};
 
-use crate::bio_format::Compression;
-use std::io::{BufRead, BufReader};
+use crate::bio_format::index::{query_chunks, read_index, IndexedReader, Query};
+use crate::bio_format::{
+    float_to_nuon, int_value, optional, parse_value, required, stream_records, Compression, Input,
+    ValueStream,
+};
+use noodles::bgzf::VirtualPosition;
+use noodles::core::{region::Interval, Position, Region};
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use super::SpanExt;
 
+mod decode;
+
+use self::decode::{read_record, BcfRecord, Field};
+
 /// Compression status of a VCF reader.
-enum VCFReader<'a> {
-    Uncompressed(Box<vcf::Reader<&'a [u8]>>),
-    Compressed(Box<vcf::Reader<BufReader<bgzf::Reader<&'a [u8]>>>>),
+enum VCFReader {
+    Uncompressed(Box<vcf::Reader<Input>>),
+    Compressed(Box<vcf::Reader<BufReader<bgzf::Reader<Input>>>>),
 }
 
 /// Compression status of a BCF reader.
-enum BCFReader<'a> {
-    Uncompressed(Box<bcf::Reader<bgzf::Reader<&'a [u8]>>>),
-    Compressed(Box<bcf::Reader<bgzf::Reader<bgzf::Reader<&'a [u8]>>>>),
+enum BCFReader {
+    Uncompressed(Box<bcf::Reader<bgzf::Reader<Input>>>),
+    Compressed(Box<bcf::Reader<bgzf::Reader<bgzf::Reader<Input>>>>),
 }
 
 /// VCF column headers
@@ -44,15 +83,157 @@ const HEADER_COLUMNS: &[&str] = &[
     "filter",
     "format",
     "alt_alleles",
-    // "assembly",
+    "assembly",
     "contig",
-    // "meta",
-    // "pedigree",
+    "meta",
+    "pedigree",
     "samples",
+    "other",
 ];
 
-/// This parses the header of a V/BCF
-fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
+/// The header columns of records which noodles keeps with any other records, and their keys.
+const OTHER_SECTIONS: &[(&str, &str)] = &[
+    ("assembly", "assembly"),
+    ("meta", "META"),
+    ("pedigree", "PEDIGREE"),
+];
+
+/// The samples to keep, and how their genotypes are laid out.
+pub struct Samples {
+    /// The sample names, with their index in the header.
+    selected: Vec<(String, usize)>,
+    /// Whether each sample gets a row of its own (`--long`).
+    long: bool,
+}
+
+/// Parse the samples passed to `--samples`, given as a list or a comma-separated string,
+/// and whether `--long` was given.
+///
+/// All samples are kept if `--samples` isn't given.
+pub fn samples_from(call: &EvaluatedCall, h: &vcf::Header) -> Result<Samples, LabeledError> {
+    let sample_names = h.sample_names();
+    let long = call.has_flag("long")?;
+
+    let Some(value) = call.get_flag_value("samples") else {
+        let selected = sample_names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), i))
+            .collect();
+        return Ok(Samples { selected, long });
+    };
+
+    let names = match &value {
+        Value::List { vals, .. } => vals.iter().map(Value::as_str).collect::<Result<_, _>>()?,
+        other => other.as_str()?.split(',').collect::<Vec<_>>(),
+    };
+
+    names
+        .into_iter()
+        .map(|name| {
+            let name = name.trim();
+            sample_names
+                .get_index_of(name)
+                .map(|i| (name.to_string(), i))
+                .ok_or_else(|| {
+                    LabeledError::new(format!("sample {name} is not in the header"))
+                        .with_label("Unknown sample.", value.span())
+                })
+        })
+        .collect::<Result<_, _>>()
+        .map(|selected| Samples { selected, long })
+}
+
+/// INFO fields holding functional annotations: SnpEff, VEP and bcftools csq.
+const ANNOTATION_FIELDS: &[&str] = &["ANN", "CSQ", "BCSQ"];
+
+/// The annotation fields to split with `--annotations`, with their subfield names.
+pub struct Annotations(Vec<(String, Vec<String>)>);
+
+/// Read the subfield names of the annotation fields in the header, if `--annotations` was
+/// given.
+///
+/// They're listed in the description, e.g. `Functional annotations: 'Allele | Annotation | ...'`
+/// for SnpEff, or `... Format: Allele|Consequence|IMPACT|...` for VEP.
+pub fn annotations_from(
+    call: &EvaluatedCall,
+    h: &vcf::Header,
+) -> Result<Annotations, LabeledError> {
+    if !call.has_flag("annotations")? {
+        return Ok(Annotations(Vec::new()));
+    }
+
+    let fields = ANNOTATION_FIELDS
+        .iter()
+        .filter_map(|&key| Some((key, h.infos().get(key)?.description())))
+        .map(|(key, description)| {
+            let format = match description.split_once("Format:") {
+                Some((_, format)) => format,
+                None => match description.split_once('\'') {
+                    Some((_, format)) => format,
+                    None => {
+                        return Err(LabeledError::new(format!(
+                            "no subfield names in the description of INFO field {key}"
+                        ))
+                        .with_label("Could not split annotations.", call.head)
+                        .with_help(
+                            "the description should list them after `Format:`, or in quotes",
+                        ))
+                    }
+                },
+            };
+            let names = format
+                .trim_matches(|c: char| c == '\'' || c == '"' || c.is_whitespace())
+                .split('|')
+                .map(|name| name.trim().to_string())
+                .collect();
+            Ok((key.to_string(), names))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Annotations(fields))
+}
+
+impl Annotations {
+    /// Split an annotation field into a list of records, one per annotation, with a column
+    /// for each subfield (`null` if empty). Other fields are left as they are.
+    fn split(&self, key: &str, value: Value, span: Span) -> Value {
+        let Some((_, names)) = self.0.iter().find(|(k, _)| k == key) else {
+            return value;
+        };
+        let split = |annotation: &str| {
+            let mut subfields = annotation.split('|');
+            let record = names
+                .iter()
+                .map(|name| {
+                    let subfield = match subfields.next() {
+                        Some(s) if !s.is_empty() => span.with_string(s),
+                        _ => Value::nothing(span),
+                    };
+                    (name.clone(), subfield)
+                })
+                .collect();
+            Value::record(record, span)
+        };
+
+        match value {
+            Value::String { val, .. } => Value::list(vec![split(&val)], span),
+            Value::List { vals, .. } => Value::list(
+                vals.into_iter()
+                    .map(|v| match v {
+                        Value::String { val, .. } => split(&val),
+                        other => other,
+                    })
+                    .collect(),
+                span,
+            ),
+            other => other,
+        }
+    }
+}
+
+/// This parses the header of a V/BCF, listing only the samples being kept.
+fn parse_header(call: &EvaluatedCall, h: &vcf::Header, samples: &Samples) -> Value {
     let file_format = call.head.with_string(h.file_format());
     let infos = h.infos();
 
@@ -135,7 +316,7 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
 
             vals.extend(f.other_fields().values().map(|e| call.head.with_string(e)));
 
-            let contig_vals_inner = Record::from_iter(cols.into_iter().zip(vals.into_iter()));
+            let contig_vals_inner = Record::from_iter(cols.into_iter().zip(vals));
 
             Value::record(contig_vals_inner, call.head)
         }),
@@ -143,18 +324,37 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
 
     let contigs_nuon = Value::record(contigs_inner, call.head);
 
-    // metadata, assembly, and pedigree are not currently parsed.
+    // the assembly, metadata and pedigree are kept with any other records, so look them up.
+    let other_records = h.other_records();
+    let section = |key: &str| {
+        other_records
+            .get(key)
+            .map(|c| collection_to_nuon(c, call.head))
+    };
+    let assembly_nuon = section("assembly").unwrap_or(Value::nothing(call.head));
+    let meta_nuon = section("META").unwrap_or(Value::record(Record::new(), call.head));
+    let pedigree_nuon = section("PEDIGREE").unwrap_or(Value::record(Record::new(), call.head));
+
+    // and everything else, keyed as it is in the header (e.g. fileDate, source).
+    let others_nuon = Value::record(
+        other_records
+            .iter()
+            .filter(|(key, _)| !OTHER_SECTIONS.iter().any(|(_, k)| key.as_ref() == *k))
+            .map(|(key, c)| (key.to_string(), collection_to_nuon(c, call.head)))
+            .collect(),
+        call.head,
+    );
 
     // sample names
     let sample_names_nuon = Value::list(
-        h.sample_names()
+        samples
+            .selected
             .iter()
-            .map(|e| call.head.with_string(e))
+            .map(|(name, _)| call.head.with_string(name))
             .collect(),
         call.head,
     );
 
-    // TODO: I've skipped other records for the moment.
     // return the big record
     Value::record(
         Record::from_iter(HEADER_COLUMNS.iter().map(|e| e.to_string()).zip(vec![
@@ -163,45 +363,495 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
             filters_nuon,
             formats_nuon,
             alt_alleles_nuon,
+            assembly_nuon,
             contigs_nuon,
+            meta_nuon,
+            pedigree_nuon,
             sample_names_nuon,
+            others_nuon,
         ])),
         call.head,
     )
 }
 
-/// Add a VCF record to the vector.
-/// TODO: make data more structured, so less is turned into a string immediately.
-fn add_record(call: &EvaluatedCall, r: vcf::Record, vec_vals: &mut Vec<Value>) {
+/// Header records other than the standard ones.
+///
+/// Structured records (e.g. `##META=<ID=...>`) are a record keyed by ID of their fields, and
+/// unstructured ones (e.g. `##fileDate=...`) a string, or a list if the key is repeated.
+fn collection_to_nuon(collection: &Collection, span: Span) -> Value {
+    match collection {
+        Collection::Unstructured(vals) => match vals.as_slice() {
+            [val] => span.with_string(val),
+            vals => Value::list(vals.iter().map(|v| span.with_string(v)).collect(), span),
+        },
+        Collection::Structured(maps) => Value::record(
+            maps.iter()
+                .map(|(id, map)| {
+                    let fields = map
+                        .other_fields()
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), span.with_string(v)))
+                        .collect();
+                    (id.clone(), Value::record(fields, span))
+                })
+                .collect(),
+            span,
+        ),
+    }
+}
+
+/// A list of values from an INFO or FORMAT array, with missing values as `null`.
+fn list<T>(vals: &[Option<T>], span: Span, f: impl Fn(&T) -> Value) -> Value {
+    Value::list(
+        vals.iter()
+            .map(|v| v.as_ref().map_or(Value::nothing(span), &f))
+            .collect(),
+        span,
+    )
+}
+
+/// Fields whose number isn't 1 are always lists, even where BCF stores a single value.
+///
+/// BCF stores strings as one, so they're split on commas as they would be in VCF.
+fn with_number(value: Value, number: Option<Number>, span: Span) -> Value {
+    match (number, value) {
+        (Some(number), Value::String { val, .. }) if number != Number::Count(1) => Value::list(
+            val.split(',')
+                .map(|v| match v {
+                    "." => Value::nothing(span),
+                    v => span.with_string(v),
+                })
+                .collect(),
+            span,
+        ),
+        (Some(number), value @ (Value::Int { .. } | Value::Float { .. }))
+            if number != Number::Count(1) =>
+        {
+            Value::list(vec![value], span)
+        }
+        (_, value) => value,
+    }
+}
+
+/// An INFO value, which noodles has already typed using its header definition.
+///
+/// Numbers other than 1 (e.g. `A`, `R` or `.`) are lists, with missing values as `null`.
+fn info_value_to_nuon(value: Option<&InfoValue>, span: Span) -> Value {
+    match value {
+        None => Value::nothing(span),
+        Some(InfoValue::Integer(i)) => Value::int(i64::from(*i), span),
+        Some(InfoValue::Float(f)) => float_to_nuon(*f, span),
+        Some(InfoValue::Flag) => Value::bool(true, span),
+        Some(InfoValue::Character(c)) => span.with_string(c),
+        Some(InfoValue::String(s)) => span.with_string(s),
+        Some(InfoValue::Array(InfoArray::Integer(vals))) => {
+            list(vals, span, |i| Value::int(i64::from(*i), span))
+        }
+        Some(InfoValue::Array(InfoArray::Float(vals))) => {
+            list(vals, span, |f| float_to_nuon(*f, span))
+        }
+        Some(InfoValue::Array(InfoArray::Character(vals))) => {
+            list(vals, span, |c| span.with_string(c))
+        }
+        Some(InfoValue::Array(InfoArray::String(vals))) => {
+            list(vals, span, |s| span.with_string(s))
+        }
+    }
+}
+
+/// The INFO column as a record, with a column for every INFO field in the header.
+///
+/// Flags which aren't set are `false`, and other missing fields are `null`, so every row
+/// has the same columns. Annotation fields are split with `--annotations`.
+fn info_to_nuon(
+    info: &Info,
+    infos: &vcf::header::Infos,
+    annotations: &Annotations,
+    span: Span,
+) -> Value {
+    let mut record = Record::new();
+
+    for (key, value) in info.as_ref() {
+        let value = info_value_to_nuon(value.as_ref(), span);
+        let number = infos.get(key).map(|d| d.number());
+        let value = with_number(value, number, span);
+        record.push(
+            key.to_string(),
+            annotations.split(key.as_ref(), value, span),
+        );
+    }
+
+    for (key, definition) in infos {
+        if !info.as_ref().contains_key(key) {
+            let value = match definition.ty() {
+                InfoType::Flag => Value::bool(false, span),
+                _ => Value::nothing(span),
+            };
+            record.push(key.to_string(), value);
+        }
+    }
+
+    Value::record(record, span)
+}
+
+/// A FORMAT value for one sample, typed like INFO values.
+fn sample_value_to_nuon(value: Option<&SampleValue>, span: Span) -> Value {
+    match value {
+        None => Value::nothing(span),
+        Some(SampleValue::Integer(i)) => Value::int(i64::from(*i), span),
+        Some(SampleValue::Float(f)) => float_to_nuon(*f, span),
+        Some(SampleValue::Character(c)) => span.with_string(c),
+        Some(SampleValue::String(s)) => span.with_string(s),
+        Some(SampleValue::Array(SampleArray::Integer(vals))) => {
+            list(vals, span, |i| Value::int(i64::from(*i), span))
+        }
+        Some(SampleValue::Array(SampleArray::Float(vals))) => {
+            list(vals, span, |f| float_to_nuon(*f, span))
+        }
+        Some(SampleValue::Array(SampleArray::Character(vals))) => {
+            list(vals, span, |c| span.with_string(c))
+        }
+        Some(SampleValue::Array(SampleArray::String(vals))) => {
+            list(vals, span, |s| span.with_string(s))
+        }
+    }
+}
+
+/// A genotype (GT) as its allele indices, `null` where missing, and whether it's phased.
+///
+/// The first allele has no phasing of its own, so it's phased if all the others are.
+fn alleles_to_nuon(alleles: impl Iterator<Item = (Option<usize>, bool)>, span: Span) -> Value {
+    let mut positions = Vec::new();
+    let mut phased = true;
+    for (i, (position, allele_phased)) in alleles.enumerate() {
+        positions.push(position.map_or(Value::nothing(span), |p| Value::int(p as i64, span)));
+        if i > 0 {
+            phased &= allele_phased;
+        }
+    }
+
+    let phased = phased && positions.len() > 1;
+
+    Value::record(
+        record! {
+            "alleles" => Value::list(positions, span),
+            "phased" => Value::bool(phased, span),
+        },
+        span,
+    )
+}
+
+/// A genotype (GT) parsed from VCF text.
+fn genotype_to_nuon(genotype: &Genotype, span: Span) -> Value {
+    alleles_to_nuon(
+        genotype
+            .iter()
+            .map(|allele| (allele.position(), allele.phasing() == Phasing::Phased)),
+        span,
+    )
+}
+
+/// The FORMAT fields of each sample being kept, in order, or `None` where the record
+/// doesn't have the sample.
+type SampleFields = Vec<Option<Record>>;
+
+/// The FORMAT fields of the samples being kept, from a VCF record's genotypes.
+fn vcf_sample_fields(
+    genotypes: &Genotypes,
+    formats: &vcf::header::Formats,
+    samples: &Samples,
+    span: Span,
+) -> Result<SampleFields, LabeledError> {
+    samples
+        .selected
+        .iter()
+        .map(|(name, i)| {
+            let Some(sample) = genotypes.get_index(*i) else {
+                return Ok(None);
+            };
+
+            let mut fields = Record::new();
+            for (key, value) in sample.keys().iter().zip(sample.values()) {
+                let value = match value {
+                    Some(SampleValue::String(gt)) if *key == key::GENOTYPE => {
+                        let genotype: Genotype = gt.parse().map_err(|e| {
+                            LabeledError::new(format!("invalid genotype {gt} for {name}: {e}"))
+                                .with_label("Could not parse genotype.", span)
+                        })?;
+                        genotype_to_nuon(&genotype, span)
+                    }
+                    value => {
+                        let number = formats.get(key).map(|d| d.number());
+                        with_number(sample_value_to_nuon(value.as_ref(), span), number, span)
+                    }
+                };
+                fields.push(key.to_string(), value);
+            }
+            Ok(Some(fields))
+        })
+        .collect()
+}
+
+/// The genotypes column as a record keyed by sample name, each a record of its FORMAT fields.
+fn genotypes_to_nuon(fields: SampleFields, samples: &Samples, span: Span) -> Value {
+    Value::record(
+        samples
+            .selected
+            .iter()
+            .zip(fields)
+            .map(|((name, _), fields)| {
+                let value = fields.map_or(Value::nothing(span), |f| Value::record(f, span));
+                (name.clone(), value)
+            })
+            .collect(),
+        span,
+    )
+}
+
+/// The `--long` rows of a variant, one per sample, with the genotype and then every FORMAT
+/// field in the header as columns (`null` where the sample doesn't have it).
+///
+/// The genotype is written as in VCF (e.g. `0|1`), so it's easy to group by, and it's an
+/// error naming the sample and variant if it can't be.
+fn long_rows(
+    span: Span,
+    header: &vcf::Header,
+    samples: &Samples,
+    columns: &[Value],
+    fields: SampleFields,
+) -> Result<Vec<Value>, LabeledError> {
+    let [chrom, pos, _, _, _, reference, alt, ..] = columns else {
+        unreachable!("a variant has all its columns");
+    };
+
+    samples
+        .selected
+        .iter()
+        .zip(fields)
+        .map(|((name, _), fields)| {
+            let value_of = |key: &str| fields.as_ref().and_then(|f| f.get(key)).cloned();
+            let gt = match value_of(key::GENOTYPE.as_ref()).filter(|gt| !gt.is_nothing()) {
+                Some(gt) => span.with_string(sample_value_to_vcf(&gt).map_err(|e| {
+                    LabeledError::new(format!(
+                        "invalid genotype for {name} at {}:{}: {}",
+                        chrom.coerce_str().unwrap_or_default(),
+                        pos.coerce_str().unwrap_or_default(),
+                        e.msg
+                    ))
+                    .with_label("Could not write the genotype.", span)
+                })?),
+                None => Value::nothing(span),
+            };
+
+            let mut row = record! {
+                "chrom" => chrom.clone(),
+                "pos" => pos.clone(),
+                "ref" => reference.clone(),
+                "alt" => alt.clone(),
+                "sample" => span.with_string(name),
+                "gt" => gt,
+            };
+            for key in header.formats().keys() {
+                if *key != key::GENOTYPE {
+                    let value = value_of(key.as_ref()).unwrap_or(Value::nothing(span));
+                    row.push(key.to_string(), value);
+                }
+            }
+
+            Ok(Value::record(row, span))
+        })
+        .collect()
+}
+
+/// The rows of a variant, from its columns up to FILTER, its INFO column, and its samples'
+/// FORMAT fields: a nushell record, or one per sample with `--long`.
+///
+/// `--long` rows don't have INFO, so it's only worked out for records.
+fn variant_rows(
+    mut columns: Vec<Value>,
+    info: impl FnOnce() -> Result<Value, LabeledError>,
+    fields: SampleFields,
+    header: &vcf::Header,
+    samples: &Samples,
+    span: Span,
+) -> Result<Vec<Value>, LabeledError> {
+    if samples.long {
+        return long_rows(span, header, samples, &columns, fields);
+    }
+
+    columns.push(info()?);
+    columns.push(genotypes_to_nuon(fields, samples, span));
+    let record_inner = Record::from_iter(VCF_COLUMNS.iter().map(|e| e.to_string()).zip(columns));
+
+    Ok(vec![Value::record(record_inner, span)])
+}
+
+/// Turn a VCF record into its rows, and where it is for error messages.
+fn variant_to_nuon(
+    r: vcf::Record,
+    header: &vcf::Header,
+    samples: &Samples,
+    annotations: &Annotations,
+    span: Span,
+) -> Result<(String, Vec<Value>), LabeledError> {
+    let here = format!("{}:{}", r.chromosome(), r.position());
     let pos = usize::from(r.position());
     let rlen = r.reference_bases().len();
 
-    let values_to_extend: Vec<Value> = vec![
-        call.head.with_string(r.chromosome()),
-        Value::int(pos as i64, call.head),
-        Value::int(rlen as i64, call.head),
-        call.head.with_string_or(r.quality_score(), ""),
-        call.head.with_string(r.ids()),
-        call.head.with_string(r.reference_bases()),
-        call.head.with_string(r.alternate_bases()),
-        call.head.with_string_or(r.filters(), ""),
-        call.head.with_string(r.info()),
-        call.head.with_string(r.genotypes()),
+    let columns = vec![
+        span.with_string(r.chromosome()),
+        Value::int(pos as i64, span),
+        Value::int(rlen as i64, span),
+        span.with_string_or(r.quality_score(), ""),
+        span.with_string(r.ids()),
+        span.with_string(r.reference_bases()),
+        span.with_string(r.alternate_bases()),
+        span.with_string_or(r.filters(), ""),
     ];
+    let info = || Ok(info_to_nuon(r.info(), header.infos(), annotations, span));
+    let fields = vcf_sample_fields(r.genotypes(), header.formats(), samples, span)?;
+
+    let rows = variant_rows(columns, info, fields, header, samples, span)?;
+    Ok((here, rows))
+}
+
+/// The FORMAT fields of the samples being kept, decoded from a BCF record without decoding
+/// the other samples.
+fn bcf_sample_fields(
+    r: &BcfRecord,
+    string_maps: &StringMaps,
+    formats: &vcf::header::Formats,
+    samples: &Samples,
+    span: Span,
+) -> io::Result<SampleFields> {
+    let indices: Vec<_> = samples.selected.iter().map(|(_, i)| *i).collect();
+    let fields = r.sample_fields(string_maps, &indices)?;
+
+    let to_nuon = |(key, field): (String, Field)| {
+        let value = match field {
+            Field::Genotype(alleles) if alleles.is_empty() => Value::nothing(span),
+            Field::Genotype(alleles) => alleles_to_nuon(alleles.into_iter(), span),
+            Field::Value(value) => {
+                let number = formats.get(key.as_str()).map(|d| d.number());
+                with_number(sample_value_to_nuon(value.as_ref(), span), number, span)
+            }
+        };
+        (key, value)
+    };
+
+    Ok(fields
+        .into_iter()
+        .map(|sample| sample.map(|fields| fields.into_iter().map(to_nuon).collect()))
+        .collect())
+}
+
+/// Turn a BCF record into its rows, and where it is for error messages.
+///
+/// The columns are decoded straight from the record: INFO only if the rows have it, and
+/// FORMAT only for the samples being kept.
+fn bcf_variant_to_nuon(
+    r: BcfRecord,
+    header: &vcf::Header,
+    string_maps: &StringMaps,
+    samples: &Samples,
+    annotations: &Annotations,
+    span: Span,
+) -> Result<(String, Vec<Value>), LabeledError> {
+    let decode_error = |e: io::Error| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label("Record reading failed.", span)
+    };
+
+    let site = &r.site;
+    let chromosome_id = site.chromosome_id();
+    let chrom = string_maps
+        .contigs()
+        .get_index(chromosome_id)
+        .ok_or_else(|| {
+            decode_error(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid chromosome ID {chromosome_id}"),
+            ))
+        })?;
+    let here = format!("{chrom}:{}", site.position());
+    let (reference, alternates) = r.alleles().map_err(decode_error)?;
+    let filters = site
+        .filters()
+        .try_into_vcf_record_filters(string_maps.strings())
+        .map_err(decode_error)?;
+
+    let columns = vec![
+        span.with_string(chrom),
+        Value::int(usize::from(site.position()) as i64, span),
+        Value::int(reference.len() as i64, span),
+        span.with_string_or(site.quality_score(), ""),
+        span.with_string(site.ids()),
+        span.with_string(reference),
+        span.with_string(alternates),
+        span.with_string_or(filters, ""),
+    ];
+    let info = || {
+        let info = site
+            .info()
+            .try_into_vcf_record_info(header, string_maps.strings())
+            .map_err(decode_error)?;
+        Ok(info_to_nuon(&info, header.infos(), annotations, span))
+    };
+    let fields = bcf_sample_fields(&r, string_maps, header.formats(), samples, span)
+        .map_err(decode_error)?;
 
-    vec_vals.extend_from_slice(&values_to_extend);
+    let rows = variant_rows(columns, info, fields, header, samples, span)?;
+    Ok((here, rows))
 }
 
-/// Read a BCF header and return the header, stringmaps, and also the header in nuon format.
+/// Lazily turn records (or the errors reading them) into a stream of rows.
+///
+/// Errors say which record failed, counting from 1, and where the last good record was, as
+/// the reader can't say where in the file it got to.
+fn variants_to_nuon<I, T, F>(records: I, to_nuon: F, span: Span) -> ValueStream
+where
+    I: Iterator<Item = io::Result<T>> + Send + 'static,
+    F: Fn(T) -> Result<(String, Vec<Value>), LabeledError> + Send + 'static,
+{
+    let mut last_good: Option<String> = None;
+
+    let rows = records.enumerate().flat_map(move |(i, record)| {
+        let rows = record
+            .map_err(|e| {
+                LabeledError::new(format!("cause of failure: {}", e))
+                    .with_label("Record reading failed.", span)
+            })
+            .and_then(&to_nuon);
+
+        match rows {
+            Ok((here, rows)) => {
+                last_good = Some(here);
+                rows.into_iter().map(Ok).collect()
+            }
+            Err(mut e) => {
+                e.msg = match &last_good {
+                    Some(last) => format!("record {} (after {last}): {}", i + 1, e.msg),
+                    None => format!("record {}: {}", i + 1, e.msg),
+                };
+                vec![Err(e)]
+            }
+        }
+    });
+
+    stream_records(rows, span)
+}
+
+/// Read a BCF header and return the header, stringmaps, the samples to keep, the annotation
+/// fields to split, and also the header in nuon format.
 fn read_bcf_header(
     reader: &mut BCFReader,
     call: &EvaluatedCall,
-) -> Result<(vcf::Header, StringMaps, Value), LabeledError> {
+) -> Result<(vcf::Header, StringMaps, Samples, Annotations, Value), LabeledError> {
     // avoid repetitive code
     fn gzip_agnostic_reader<R: BufRead>(
         r: &mut bcf::Reader<R>,
         call: &EvaluatedCall,
-    ) -> Result<(vcf::Header, StringMaps, Value), LabeledError> {
+    ) -> Result<(vcf::Header, StringMaps, Samples, Annotations, Value), LabeledError> {
         let raw_header = match r.read_header() {
             Ok(e) => e,
             Err(e) => {
@@ -210,11 +860,12 @@ fn read_bcf_header(
             }
         };
 
-        let header_nuon = parse_header(call, &raw_header);
-        // TODO: remove this unwrap
+        let samples = samples_from(call, &raw_header)?;
+        let annotations = annotations_from(call, &raw_header)?;
+        let header_nuon = parse_header(call, &raw_header, &samples);
         let string_maps = r.string_maps().clone();
 
-        Ok((raw_header, string_maps, header_nuon))
+        Ok((raw_header, string_maps, samples, annotations, header_nuon))
     }
 
     match reader {
@@ -223,96 +874,65 @@ fn read_bcf_header(
     }
 }
 
-/// Generic function for optional compression to iterate over the BCF records.
-fn iterate_bcf_records<R: BufRead>(
+/// Generic function for optional compression to lazily iterate over the BCF records.
+fn iterate_bcf_records<R: BufRead + Send + 'static>(
     mut reader: bcf::Reader<R>,
     header: vcf::Header,
-    _string_maps: StringMaps,
-    call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
-) -> Result<(), LabeledError> {
-    for record in reader.records(&header) {
-        let r = match record {
-            Ok(rec) => rec,
-            Err(e) => {
-                return Err(LabeledError::new(format!("cause of failure: {}", e))
-                    .with_label("Record reading failed.", call.head))
-            }
-        };
-
-        let mut vec_vals = Vec::new();
-        add_record(call, r, &mut vec_vals);
-
-        let record_inner =
-            Record::from_iter(VCF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+    string_maps: StringMaps,
+    samples: Samples,
+    annotations: Annotations,
+    span: Span,
+) -> ValueStream {
+    let records = std::iter::from_fn(move || read_record(reader.get_mut()));
 
-        value_records.push(Value::record(record_inner, call.head))
-    }
-
-    Ok(())
+    variants_to_nuon(
+        records,
+        move |r| bcf_variant_to_nuon(r, &header, &string_maps, &samples, &annotations, span),
+        span,
+    )
 }
 
-/// Parse a fasta file into a nushell structure.
+/// Parse a BCF file into its header and a stream of records.
 pub fn from_bcf_inner(
     call: &EvaluatedCall,
-    input: &Value,
+    input: Input,
     gz: &Compression,
-) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val,
-        other => {
-            return Err(LabeledError::new(format!(
-                "requires binary input, got {}",
-                other.get_type()
-            ))
-            .with_label("Input should be binary.", call.head))
-        }
-    };
-
+) -> Result<(Value, ValueStream), LabeledError> {
     let mut reader = match gz {
-        Compression::Uncompressed => {
-            BCFReader::Uncompressed(Box::new(bcf::Reader::new(stream.as_slice())))
-        }
+        Compression::Uncompressed => BCFReader::Uncompressed(Box::new(bcf::Reader::new(input))),
         Compression::Gzipped => {
-            let gz = bgzf::Reader::new(stream.as_slice());
+            let gz = bgzf::Reader::new(input);
             BCFReader::Compressed(Box::new(bcf::Reader::new(gz)))
         }
     };
 
-    let (header, string_maps, header_nuon) = read_bcf_header(&mut reader, call).unwrap();
-
-    let mut value_records = Vec::new();
+    let (header, string_maps, samples, annotations, header_nuon) =
+        read_bcf_header(&mut reader, call)?;
 
     // now match on compression
-    match reader {
+    let body = match reader {
         BCFReader::Uncompressed(uc) => {
-            iterate_bcf_records(*uc, header, string_maps, call, &mut value_records).unwrap();
+            iterate_bcf_records(*uc, header, string_maps, samples, annotations, call.head)
         }
         BCFReader::Compressed(c) => {
-            iterate_bcf_records(*c, header, string_maps, call, &mut value_records).unwrap();
+            iterate_bcf_records(*c, header, string_maps, samples, annotations, call.head)
         }
-    }
+    };
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head),
-        },
-        call.head,
-    ))
+    Ok((header_nuon, body))
 }
 
-/// Read a VCF header and return the header, stringmaps, and also the header in nuon format.
+/// Read a VCF header and return the header, the samples to keep, the annotation fields to
+/// split, and also the header in nuon format.
 fn read_vcf_header(
     reader: &mut VCFReader,
     call: &EvaluatedCall,
-) -> Result<(vcf::Header, Value), LabeledError> {
+) -> Result<(vcf::Header, Samples, Annotations, Value), LabeledError> {
     // avoid repetitive code
     fn gzip_agnostic_reader<R: BufRead>(
         r: &mut vcf::Reader<R>,
         call: &EvaluatedCall,
-    ) -> Result<(vcf::Header, Value), LabeledError> {
+    ) -> Result<(vcf::Header, Samples, Annotations, Value), LabeledError> {
         // get the raw header
         let raw_header = match r.read_header() {
             Ok(rh) => rh,
@@ -322,9 +942,11 @@ fn read_vcf_header(
             }
         };
 
-        let header_nuon = parse_header(call, &raw_header);
+        let samples = samples_from(call, &raw_header)?;
+        let annotations = annotations_from(call, &raw_header)?;
+        let header_nuon = parse_header(call, &raw_header, &samples);
 
-        Ok((raw_header, header_nuon))
+        Ok((raw_header, samples, annotations, header_nuon))
     }
 
     match reader {
@@ -333,79 +955,587 @@ fn read_vcf_header(
     }
 }
 
-/// Generic function for optional compression to iterate over the VCF records.
-fn iterate_vcf_records<R: BufRead>(
+/// Generic function for optional compression to lazily iterate over the VCF records.
+fn iterate_vcf_records<R: BufRead + Send + 'static>(
     mut reader: vcf::Reader<R>,
     header: vcf::Header,
+    samples: Samples,
+    annotations: Annotations,
+    span: Span,
+) -> ValueStream {
+    let records = {
+        let header = header.clone();
+        std::iter::from_fn(move || reader.records(&header).next())
+    };
+
+    variants_to_nuon(
+        records,
+        move |r| variant_to_nuon(r, &header, &samples, &annotations, span),
+        span,
+    )
+}
+
+/// Parse a VCF file into its header and a stream of records.
+pub fn from_vcf_inner(
+    call: &EvaluatedCall,
+    input: Input,
+    gz: &Compression,
+) -> Result<(Value, ValueStream), LabeledError> {
+    let mut reader = match gz {
+        Compression::Uncompressed => VCFReader::Uncompressed(Box::new(vcf::Reader::new(input))),
+        Compression::Gzipped => {
+            let gz = bgzf::Reader::new(input);
+            VCFReader::Compressed(Box::new(vcf::Reader::new(BufReader::new(gz))))
+        }
+    };
+
+    let (header, samples, annotations, header_nuon) = read_vcf_header(&mut reader, call)?;
+
+    // now match on compression
+    let body = match reader {
+        VCFReader::Uncompressed(uc) => {
+            iterate_vcf_records(*uc, header, samples, annotations, call.head)
+        }
+        VCFReader::Compressed(c) => {
+            iterate_vcf_records(*c, header, samples, annotations, call.head)
+        }
+    };
+
+    Ok((header_nuon, body))
+}
+
+/// Whether a variant overlaps a region.
+fn variant_intersects(record: &vcf::Record, region: &Region) -> io::Result<bool> {
+    let start = Position::try_from(usize::from(record.position()))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let end = record
+        .end()
+        .map(usize::from)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        .and_then(|n| {
+            Position::try_from(n).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })?;
+
+    Ok(record.chromosome().to_string() == region.name()
+        && Interval::from(start..=end).intersects(region.interval()))
+}
+
+/// A bgzipped VCF, along with the header needed to decode its records.
+struct IndexedVcf {
+    reader: vcf::Reader<bgzf::Reader<File>>,
+    header: vcf::Header,
+}
+
+impl IndexedReader for IndexedVcf {
+    type Record = vcf::Record;
+
+    fn seek(&mut self, pos: VirtualPosition) -> io::Result<VirtualPosition> {
+        self.reader.seek(pos)
+    }
+
+    fn virtual_position(&self) -> VirtualPosition {
+        self.reader.virtual_position()
+    }
+
+    fn read_next(&mut self) -> Option<io::Result<vcf::Record>> {
+        self.reader.records(&self.header).next()
+    }
+
+    fn intersects(&self, record: &vcf::Record, _: usize, region: &Region) -> io::Result<bool> {
+        variant_intersects(record, region)
+    }
+}
+
+/// A BCF, whose records are decoded once they're known to be in a queried region.
+struct IndexedBcf {
+    reader: bcf::Reader<bgzf::Reader<File>>,
+}
+
+impl IndexedReader for IndexedBcf {
+    type Record = BcfRecord;
+
+    fn seek(&mut self, pos: VirtualPosition) -> io::Result<VirtualPosition> {
+        self.reader.seek(pos)
+    }
+
+    fn virtual_position(&self) -> VirtualPosition {
+        self.reader.virtual_position()
+    }
+
+    fn read_next(&mut self) -> Option<io::Result<BcfRecord>> {
+        read_record(self.reader.get_mut())
+    }
+
+    fn intersects(
+        &self,
+        record: &BcfRecord,
+        reference_sequence_id: usize,
+        region: &Region,
+    ) -> io::Result<bool> {
+        let start = Position::try_from(usize::from(record.site.position()))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let end = Position::try_from(usize::from(record.site.end()?))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(record.site.chromosome_id() == reference_sequence_id
+            && Interval::from(start..=end).intersects(region.interval()))
+    }
+}
+
+/// Open a file for a region query.
+fn open_indexed(call: &EvaluatedCall, path: &Path) -> Result<File, LabeledError> {
+    File::open(path).map_err(|e| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label(format!("Could not open {}.", path.display()), call.head)
+    })
+}
+
+/// Query regions of a bgzipped VCF with its tabix or CSI index, returning its header and
+/// a stream of the variants overlapping any of the regions.
+pub fn query_vcf_inner(
     call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
-) -> Result<(), LabeledError> {
-    for record in reader.records(&header) {
-        let r = match record {
-            Ok(rec) => rec,
-            Err(e) => {
-                return Err(LabeledError::new(format!("cause of failure: {}", e))
-                    .with_label("Record reading failed.", call.head))
+    path: &Path,
+    index: Option<PathBuf>,
+    regions: Vec<Region>,
+) -> Result<(Value, ValueStream), LabeledError> {
+    let mut reader = vcf::Reader::new(bgzf::Reader::new(open_indexed(call, path)?));
+    let header = reader.read_header().map_err(|e| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label("Failed to read raw VCF header.", call.head)
+    })?;
+    let samples = samples_from(call, &header)?;
+    let annotations = annotations_from(call, &header)?;
+    let header_nuon = parse_header(call, &header, &samples);
+
+    let index = read_index(call, path, index, &["tbi", "csi"])?;
+    let chunks = query_chunks(call, &index, regions, |name| {
+        index
+            .header()
+            .and_then(|h| h.reference_sequence_names().get_index_of(name))
+    })?;
+
+    let span = call.head;
+    let indexed = IndexedVcf {
+        reader,
+        header: header.clone(),
+    };
+    let records = Query::new(indexed, chunks);
+    let to_nuon = move |r| variant_to_nuon(r, &header, &samples, &annotations, span);
+
+    Ok((header_nuon, variants_to_nuon(records, to_nuon, span)))
+}
+
+/// Query regions of a BCF with its CSI index, returning its header and a stream of the
+/// variants overlapping any of the regions.
+pub fn query_bcf_inner(
+    call: &EvaluatedCall,
+    path: &Path,
+    index: Option<PathBuf>,
+    regions: Vec<Region>,
+) -> Result<(Value, ValueStream), LabeledError> {
+    let mut reader = bcf::Reader::new(open_indexed(call, path)?);
+    let header = reader.read_header().map_err(|e| {
+        LabeledError::new(format!("header unreadable due to {}", e))
+            .with_label("Could not read header.", call.head)
+    })?;
+    let samples = samples_from(call, &header)?;
+    let annotations = annotations_from(call, &header)?;
+    let header_nuon = parse_header(call, &header, &samples);
+
+    let index = read_index(call, path, index, &["csi"])?;
+    let contigs = reader.string_maps().contigs();
+    let chunks = query_chunks(call, &index, regions, |name| contigs.get_index_of(name))?;
+
+    let span = call.head;
+    let string_maps = reader.string_maps().clone();
+    let records = Query::new(IndexedBcf { reader }, chunks);
+    let to_nuon =
+        move |r| bcf_variant_to_nuon(r, &header, &string_maps, &samples, &annotations, span);
+
+    Ok((header_nuon, variants_to_nuon(records, to_nuon, span)))
+}
+
+/// The entries of a header section, which is a record keyed by ID.
+fn header_section<'a>(
+    header: &'a Record,
+    section: &str,
+) -> Result<impl Iterator<Item = (&'a String, &'a Record)>, LabeledError> {
+    let entries = match optional(header, section) {
+        Some(value) => value.as_record()?.iter().collect(),
+        None => Vec::new(),
+    };
+
+    entries
+        .into_iter()
+        .map(|(id, value)| Ok((id, value.as_record()?)))
+        .collect::<Result<Vec<_>, LabeledError>>()
+        .map(Vec::into_iter)
+}
+
+/// Parse an ID from a header section into a noodles type.
+fn parse_id<T>(id: &str, section: &str, span: Span) -> Result<T, LabeledError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    id.parse().map_err(|e| {
+        LabeledError::new(format!("invalid {section} ID {id}: {e}"))
+            .with_label("Could not parse header ID.", span)
+    })
+}
+
+/// A contig's header record. Other fields are kept, and a length of 0 means it's missing.
+fn nuon_to_contig(contig: &Record, span: Span) -> Result<Map<Contig>, LabeledError> {
+    let mut builder = Map::<Contig>::builder();
+
+    for (key, value) in contig.iter() {
+        if key == "length" {
+            let length = int_value(value, "contig length")?;
+            if length != 0 {
+                builder = builder.set_length(length);
             }
-        };
+        } else {
+            builder = builder.insert(parse_id(key, "contig field", span)?, value.as_str()?);
+        }
+    }
 
-        let mut vec_vals = Vec::new();
-        add_record(call, r, &mut vec_vals);
+    builder.build().map_err(|e| {
+        LabeledError::new(format!("cause of failure: {e}"))
+            .with_label("Could not build contig.", span)
+    })
+}
 
-        let vec_vals_inner =
-            Record::from_iter(VCF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+/// Add header records other than the standard ones, as returned by [`collection_to_nuon`].
+fn add_other_records(
+    mut builder: vcf::header::Builder,
+    key: &str,
+    value: &Value,
+) -> Result<vcf::header::Builder, LabeledError> {
+    let span = value.span();
+    let key: vcf::header::record::key::Other = parse_id(key, "header record", span)?;
+    let add_error = |e: vcf::header::record::value::collection::AddError| {
+        LabeledError::new(format!("cause of failure: {e}"))
+            .with_label(format!("Could not add the {key} header records."), span)
+    };
 
-        value_records.push(Value::record(vec_vals_inner, call.head))
+    match value {
+        Value::Nothing { .. } => {}
+        Value::List { vals, .. } => {
+            for val in vals {
+                let record = HeaderValue::String(val.as_str()?.into());
+                builder = builder.insert(key.clone(), record).map_err(add_error)?;
+            }
+        }
+        Value::Record { val: maps, .. } => {
+            for (id, fields) in maps.iter() {
+                let mut map = Map::<Other>::builder();
+                for (field, value) in fields.as_record()?.iter() {
+                    map = map.insert(parse_id(field, "header field", span)?, value.as_str()?);
+                }
+                let map = map.build().map_err(|e| {
+                    LabeledError::new(format!("cause of failure: {e}"))
+                        .with_label(format!("Could not build the {key} header record."), span)
+                })?;
+                let record = HeaderValue::Map(id.clone(), map);
+                builder = builder.insert(key.clone(), record).map_err(add_error)?;
+            }
+        }
+        other => {
+            let record = HeaderValue::String(other.coerce_string()?);
+            builder = builder.insert(key.clone(), record).map_err(add_error)?;
+        }
     }
 
-    Ok(())
+    Ok(builder)
 }
 
-/// Parse a fasta file into a nushell structure.
-pub fn from_vcf_inner(
-    call: &EvaluatedCall,
-    input: &Value,
-    gz: &Compression,
-) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input.as_binary() {
-        Ok(s) => s,
-        Err(e) => {
-            return Err(LabeledError::new(format!("cause of failure: {}", e))
-                .with_label("Could not stream input as binary.", call.head))
+/// Build a VCF header from the header record returned by [`parse_header`].
+fn nuon_to_header(header: &Value) -> Result<vcf::Header, LabeledError> {
+    let span = header.span();
+    let header = header.as_record()?;
+    let mut builder = vcf::Header::builder();
+
+    if let Some(file_format) = optional(header, HEADER_COLUMNS[0]) {
+        builder = builder.set_file_format(parse_value(file_format, "file_format")?);
+    }
+    for (id, info) in header_section(header, HEADER_COLUMNS[1])? {
+        let map = Map::<InfoMap>::new(
+            parse_value(required(info, "number", span)?, "number")?,
+            parse_value(required(info, "type", span)?, "type")?,
+            required(info, "description", span)?.as_str()?,
+        );
+        builder = builder.add_info(parse_id(id, "INFO", span)?, map);
+    }
+    for (id, filter) in header_section(header, HEADER_COLUMNS[2])? {
+        let description = required(filter, "description", span)?.as_str()?;
+        builder = builder.add_filter(id, Map::<Filter>::new(description));
+    }
+    for (id, format) in header_section(header, HEADER_COLUMNS[3])? {
+        let map = Map::<Format>::new(
+            parse_value(required(format, "number", span)?, "number")?,
+            parse_value(required(format, "type", span)?, "type")?,
+            required(format, "description", span)?.as_str()?,
+        );
+        builder = builder.add_format(parse_id(id, "FORMAT", span)?, map);
+    }
+    for (id, alt) in header_section(header, HEADER_COLUMNS[4])? {
+        let description = required(alt, "description", span)?.as_str()?;
+        builder = builder.add_alternative_allele(
+            parse_id(id, "ALT", span)?,
+            Map::<AlternativeAllele>::new(description),
+        );
+    }
+    for (id, contig) in header_section(header, HEADER_COLUMNS[6])? {
+        builder = builder.add_contig(parse_id(id, "contig", span)?, nuon_to_contig(contig, span)?);
+    }
+    for (column, key) in OTHER_SECTIONS {
+        if let Some(value) = optional(header, column) {
+            builder = add_other_records(builder, key, value)?;
         }
+    }
+    if let Some(samples) = optional(header, HEADER_COLUMNS[9]) {
+        for sample in samples.as_list()? {
+            builder = builder.add_sample_name(sample.as_str()?);
+        }
+    }
+    if let Some(others) = optional(header, HEADER_COLUMNS[10]) {
+        for (key, value) in others.as_record()?.iter() {
+            builder = add_other_records(builder, key, value)?;
+        }
+    }
+
+    Ok(builder.build())
+}
+
+/// A column of a variant as VCF text, with `.` for missing values.
+fn vcf_field(row: &Record, column: &str) -> Result<String, LabeledError> {
+    let field = match optional(row, column) {
+        Some(Value::String { val, .. }) => val.clone(),
+        Some(Value::Int { val, .. }) => val.to_string(),
+        Some(Value::Float { val, .. }) => val.to_string(),
+        Some(other) => other.coerce_string()?,
+        None => String::new(),
     };
 
-    let mut reader = match gz {
-        Compression::Uncompressed => VCFReader::Uncompressed(Box::new(vcf::Reader::new(stream))),
-        Compression::Gzipped => {
-            let gz = bgzf::Reader::new(stream);
-            VCFReader::Compressed(Box::new(vcf::Reader::new(BufReader::new(gz))))
+    Ok(if field.is_empty() { ".".into() } else { field })
+}
+
+/// A typed INFO value as VCF text, with `.` for missing values.
+///
+/// Split annotations (from `--annotations`) are joined back up with `|`.
+fn info_value_to_vcf(value: &Value) -> Result<String, LabeledError> {
+    Ok(match value {
+        Value::Nothing { .. } => ".".into(),
+        Value::Record { val, .. } => val
+            .values()
+            .map(|v| match v {
+                Value::Nothing { .. } => Ok(String::new()),
+                v => info_value_to_vcf(v),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .join("|"),
+        Value::List { vals, .. } => vals
+            .iter()
+            .map(info_value_to_vcf)
+            .collect::<Result<Vec<_>, _>>()?
+            .join(","),
+        Value::Float { val, .. } => val.to_string(),
+        other => other.coerce_string()?,
+    })
+}
+
+/// The INFO column as VCF text, from its record (or a string of it).
+///
+/// Flags are written if `true`, and missing fields are left out.
+fn info_to_vcf(row: &Record) -> Result<String, LabeledError> {
+    let Some(Value::Record { val: info, .. }) = optional(row, "info") else {
+        return vcf_field(row, "info");
+    };
+
+    let mut fields = Vec::new();
+    for (key, value) in info.iter() {
+        match value {
+            Value::Nothing { .. } | Value::Bool { val: false, .. } => {}
+            Value::Bool { val: true, .. } => fields.push(key.clone()),
+            value => fields.push(format!("{key}={}", info_value_to_vcf(value)?)),
         }
+    }
+
+    Ok(if fields.is_empty() {
+        ".".into()
+    } else {
+        fields.join(";")
+    })
+}
+
+/// A FORMAT value for one sample as VCF text, writing a genotype record as e.g. `0|1`.
+fn sample_value_to_vcf(value: &Value) -> Result<String, LabeledError> {
+    let Value::Record { val: genotype, .. } = value else {
+        return info_value_to_vcf(value);
     };
 
-    let (header, header_nuon) = match read_vcf_header(&mut reader, call) {
-        Ok(h) => h,
-        Err(e) => return Err(e),
+    let alleles = optional(genotype, "alleles")
+        .map(Value::as_list)
+        .transpose()?
+        .unwrap_or_default()
+        .iter()
+        .map(info_value_to_vcf)
+        .collect::<Result<Vec<_>, _>>()?;
+    let phased = optional(genotype, "phased")
+        .map(Value::as_bool)
+        .transpose()?
+        .unwrap_or(false);
+
+    Ok(if alleles.is_empty() {
+        ".".into()
+    } else {
+        alleles.join(if phased { "|" } else { "/" })
+    })
+}
+
+/// The FORMAT and sample columns as VCF text, from the genotypes record (or a string of it).
+///
+/// Samples are written in the order of the header, and the keys are those of any sample.
+fn genotypes_to_vcf(header: &vcf::Header, row: &Record) -> Result<String, LabeledError> {
+    let Some(Value::Record { val: genotypes, .. }) = optional(row, "genotypes") else {
+        return vcf_field(row, "genotypes");
     };
 
-    let mut value_records = Vec::new();
+    let mut keys: Vec<&str> = Vec::new();
+    for (_, sample) in genotypes.iter() {
+        if let Value::Record { val: fields, .. } = sample {
+            for key in fields.columns() {
+                if !keys.contains(&key.as_str()) {
+                    keys.push(key);
+                }
+            }
+        }
+    }
+    if keys.is_empty() {
+        return Ok(".".into());
+    }
 
-    // now match on compression
-    match reader {
-        VCFReader::Uncompressed(uc) => {
-            iterate_vcf_records(*uc, header, call, &mut value_records).unwrap();
+    let mut columns = vec![keys.join(":")];
+    for name in header.sample_names() {
+        let fields = match genotypes.get(name) {
+            Some(Value::Record { val: fields, .. }) => Some(fields),
+            _ => None,
+        };
+        let values = keys
+            .iter()
+            .map(|key| match fields.and_then(|f| f.get(*key)) {
+                Some(value) => sample_value_to_vcf(value),
+                None => Ok(".".into()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        columns.push(values.join(":"));
+    }
+
+    Ok(columns.join("\t"))
+}
+
+/// Build a VCF record from a row of the body, as returned by [`variant_to_nuon`].
+///
+/// `rlen` is ignored, as it follows from the reference bases.
+fn nuon_to_variant(header: &vcf::Header, row: &Value) -> Result<vcf::Record, LabeledError> {
+    let span = row.span();
+    let row = row.as_record()?;
+
+    let mut fields = ["chrom", "pos", "id", "ref", "alt", "qual", "filter"]
+        .iter()
+        .map(|column| vcf_field(row, column))
+        .collect::<Result<Vec<_>, _>>()?;
+    fields.push(info_to_vcf(row)?);
+
+    // the genotypes column holds the FORMAT and sample columns.
+    let genotypes = genotypes_to_vcf(header, row)?;
+    if genotypes != "." {
+        fields.push(genotypes);
+    }
+
+    vcf::Record::try_from((header, fields.join("\t").as_str())).map_err(|e| {
+        LabeledError::new(format!("cause of failure: {e}")).with_label("Invalid variant.", span)
+    })
+}
+
+/// Write the `{header, body}` record returned by `from vcf`/`from bcf` with a VCF or BCF writer.
+fn write_variants(
+    call: &EvaluatedCall,
+    input: &Value,
+    writer: &mut dyn VariantWriter,
+) -> Result<(), LabeledError> {
+    let write_error = |e: io::Error| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label("Could not write variants.", call.head)
+    };
+
+    let input = input.as_record().map_err(|_| {
+        LabeledError::new("expected a record of the header and the body")
+            .with_label("Input should be a record.", call.head)
+            .with_help("pass the output of `from vcf --header` or `from bcf --header`")
+    })?;
+    let header = nuon_to_header(required(input, "header", call.head)?)?;
+    let body = required(input, "body", call.head)?.as_list()?;
+
+    writer.write_variant_header(&header).map_err(write_error)?;
+
+    for (i, row) in body.iter().enumerate() {
+        let record = nuon_to_variant(&header, row).map_err(|e| {
+            let msg = e.msg.clone();
+            e.with_label(format!("Could not write record {i}: {msg}."), call.head)
+        })?;
+        writer
+            .write_variant_record(&header, &record)
+            .map_err(write_error)?;
+    }
+
+    Ok(())
+}
+
+/// Finish a BGZF stream, returning the compressed bytes.
+fn finish_bgzf(
+    call: &EvaluatedCall,
+    writer: bgzf::Writer<Vec<u8>>,
+) -> Result<Vec<u8>, LabeledError> {
+    writer.finish().map_err(|e| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label("Could not finish the BGZF stream.", call.head)
+    })
+}
+
+/// Write variants out as VCF text, or as BGZF compressed VCF bytes.
+pub fn nuon_to_vcf(
+    call: &EvaluatedCall,
+    input: &Value,
+    gz: &Compression,
+) -> Result<Value, LabeledError> {
+    match gz {
+        Compression::Uncompressed => {
+            let mut writer = vcf::Writer::new(Vec::new());
+            write_variants(call, input, &mut writer)?;
+
+            let out = String::from_utf8(writer.into_inner()).map_err(|err| {
+                LabeledError::new(err.to_string())
+                    .with_label("Can't format bytes as UTF-8", call.head)
+            })?;
+            Ok(Value::string(out, call.head))
         }
-        VCFReader::Compressed(c) => {
-            iterate_vcf_records(*c, header, call, &mut value_records).unwrap();
+        Compression::Gzipped => {
+            let mut writer = vcf::Writer::new(bgzf::Writer::new(Vec::new()));
+            write_variants(call, input, &mut writer)?;
+
+            let out = finish_bgzf(call, writer.into_inner())?;
+            Ok(Value::binary(out, call.head))
         }
     }
+}
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head),
-        },
-        call.head,
-    ))
+/// Write variants out as BCF bytes.
+pub fn nuon_to_bcf(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let mut writer = bcf::Writer::new(Vec::new());
+    write_variants(call, input, &mut writer)?;
+
+    let out = finish_bgzf(call, writer.into_inner())?;
+    Ok(Value::binary(out, call.head))
 }
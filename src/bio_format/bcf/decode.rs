@@ -0,0 +1,311 @@
+//! BCF records read as noodles lazy records, with the alleles and per-sample data decoded
+//! straight from the record as they're asked for.
+//!
+//! noodles only converts a lazy record's FORMAT fields for every sample at once, and keeps the
+//! reference and alternate alleles crate-private. So a record is parsed once without its
+//! samples, for the fixed columns, FILTER and INFO, and the alleles and the FORMAT fields of
+//! the samples being kept are read from the raw data.
+
+use std::io::{self, Read};
+use std::ops::Range;
+use std::str;
+
+use noodles::bcf::{self, header::StringMaps, lazy};
+use noodles::vcf::record::genotypes::{keys::key, sample::Value};
+
+/// Where `n_allele` (the top 16 bits) is in a record's shared data.
+const N_ALLELE_INFO: Range<usize> = 16..20;
+/// Where `n_fmt` (the top 8 bits) and `n_sample` are in a record's shared data.
+const N_FMT_SAMPLE: Range<usize> = 20..24;
+/// Where the ID starts in a record's shared data, followed by the alleles.
+const ID_START: usize = 24;
+
+/// A BCF record, parsed without its samples, along with the data needed to decode them.
+pub struct BcfRecord {
+    /// The record without its per-sample data.
+    pub site: lazy::Record,
+    /// The length prefix and shared data, with `n_fmt` and `n_sample` zeroed.
+    buf: Vec<u8>,
+    format_count: usize,
+    sample_count: usize,
+    indiv: Vec<u8>,
+}
+
+/// A FORMAT field of a sample.
+pub enum Field {
+    /// GT, as its allele indices (`None` where missing) and whether each is phased.
+    Genotype(Vec<(Option<usize>, bool)>),
+    Value(Option<Value>),
+}
+
+/// The FORMAT fields of a sample, keyed by name, in the order they're in the record.
+pub type Fields = Vec<(String, Field)>;
+
+impl BcfRecord {
+    /// The reference allele, and the alternate alleles, comma separated.
+    pub fn alleles(&self) -> io::Result<(String, String)> {
+        let shared = &self.buf[8..];
+        let n_allele = u32::from_le_bytes(shared[N_ALLELE_INFO].try_into().unwrap()) >> 16;
+
+        let mut src = &shared[ID_START..];
+        read_value(&mut src)?;
+
+        let mut alleles = (0..n_allele).map(|_| {
+            let (_, allele) = read_value(&mut src)?;
+            string(allele).map(str::to_string)
+        });
+        let reference = alleles.next().transpose()?.unwrap_or_default();
+        let alternates = alleles.collect::<io::Result<Vec<_>>>()?;
+
+        Ok((reference, alternates.join(",")))
+    }
+
+    /// The FORMAT fields of some samples, by their index in the header, in the order they're
+    /// in the record. Only the data of these samples is decoded, and `None` is returned for
+    /// any the record doesn't have.
+    pub fn sample_fields(
+        &self,
+        string_maps: &StringMaps,
+        samples: &[usize],
+    ) -> io::Result<Vec<Option<Fields>>> {
+        let mut fields: Vec<_> = samples
+            .iter()
+            .map(|&i| (i < self.sample_count).then(Vec::new))
+            .collect();
+        if fields.iter().all(Option::is_none) {
+            return Ok(fields);
+        }
+
+        let mut src = &self.indiv[..];
+        for _ in 0..self.format_count {
+            let key = read_int(&mut src)?
+                .and_then(|i| usize::try_from(i).ok())
+                .and_then(|i| string_maps.strings().get_index(i))
+                .ok_or_else(|| invalid_data("invalid FORMAT key"))?
+                .to_string();
+            let (ty, len) = read_type(&mut src)?;
+            let width = bytes(ty, len)?;
+            let all = width
+                .checked_mul(self.sample_count)
+                .ok_or_else(|| invalid_data("invalid BCF value length"))?;
+            let values = take(&mut src, all)?;
+
+            for (&i, sample) in samples.iter().zip(&mut fields) {
+                if let Some(sample) = sample {
+                    let value = &values[i * width..(i + 1) * width];
+                    let field = if key == key::GENOTYPE.as_ref() {
+                        Field::Genotype(genotype(ty, value)?)
+                    } else {
+                        Field::Value(sample_value(ty, len, value)?)
+                    };
+                    sample.push((key.clone(), field));
+                }
+            }
+        }
+
+        Ok(fields)
+    }
+}
+
+/// Read the next record, or `None` at the end of the file. A file which ends partway through
+/// a record's lengths is an error.
+pub fn read_record<R: Read>(reader: &mut R) -> Option<io::Result<BcfRecord>> {
+    let mut lengths = [0; 8];
+    let mut read = 0;
+    while read < lengths.len() {
+        match reader.read(&mut lengths[read..]) {
+            Ok(0) if read == 0 => return None,
+            Ok(0) => return Some(Err(invalid_data("truncated BCF record"))),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Some(Err(e)),
+        }
+    }
+    Some(read_record_body(reader, lengths))
+}
+
+/// Read a record given the lengths of its shared and per-sample data, parsing the shared data
+/// as a lazy record with no samples.
+fn read_record_body<R: Read>(reader: &mut R, lengths: [u8; 8]) -> io::Result<BcfRecord> {
+    let shared_len = u32::from_le_bytes(lengths[..4].try_into().unwrap()) as usize;
+    let indiv_len = u32::from_le_bytes(lengths[4..].try_into().unwrap()) as usize;
+
+    let mut buf = vec![0; 8 + shared_len];
+    buf[..4].copy_from_slice(&lengths[..4]);
+    reader.read_exact(&mut buf[8..])?;
+    let mut indiv = vec![0; indiv_len];
+    reader.read_exact(&mut indiv)?;
+
+    let n_fmt_sample = buf
+        .get_mut(8 + N_FMT_SAMPLE.start..8 + N_FMT_SAMPLE.end)
+        .ok_or_else(|| invalid_data("truncated BCF record"))?;
+    let n_fmt_sample_value = u32::from_le_bytes((&*n_fmt_sample).try_into().unwrap());
+    n_fmt_sample.fill(0);
+
+    let mut site = lazy::Record::default();
+    bcf::Reader::from(&buf[..]).read_lazy_record(&mut site)?;
+
+    Ok(BcfRecord {
+        site,
+        buf,
+        format_count: (n_fmt_sample_value >> 24) as usize,
+        sample_count: (n_fmt_sample_value & 0xff_ffff) as usize,
+        indiv,
+    })
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Take the next `len` bytes.
+fn take<'a>(src: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if src.len() < len {
+        return Err(invalid_data("truncated BCF record"));
+    }
+    let (taken, rest) = src.split_at(len);
+    *src = rest;
+    Ok(taken)
+}
+
+/// The size in bytes of a value of a BCF type: 1, 2 and 3 are 8, 16 and 32 bit ints, 5 a
+/// float, 7 a character, and 0 has no values.
+fn size_of(ty: u8) -> io::Result<usize> {
+    match ty {
+        0 => Ok(0),
+        1 | 7 => Ok(1),
+        2 => Ok(2),
+        3 | 5 => Ok(4),
+        ty => Err(invalid_data(&format!("invalid BCF type {ty}"))),
+    }
+}
+
+/// The size in bytes of `len` values of a type.
+fn bytes(ty: u8, len: usize) -> io::Result<usize> {
+    len.checked_mul(size_of(ty)?)
+        .ok_or_else(|| invalid_data("invalid BCF value length"))
+}
+
+/// A typed value, e.g. the ID or an allele, as its type and data.
+fn read_value<'a>(src: &mut &'a [u8]) -> io::Result<(u8, &'a [u8])> {
+    let (ty, len) = read_type(src)?;
+    let data = take(src, bytes(ty, len)?)?;
+    Ok((ty, data))
+}
+
+/// A type descriptor: the type, and the number of values, which follows as an int if it's 15
+/// or more.
+fn read_type(src: &mut &[u8]) -> io::Result<(u8, usize)> {
+    let descriptor = take(src, 1)?[0];
+    let mut len = usize::from(descriptor >> 4);
+    if len == 15 {
+        len = read_int(src)?
+            .and_then(|n| usize::try_from(n).ok())
+            .ok_or_else(|| invalid_data("invalid BCF value length"))?;
+    }
+    Ok((descriptor & 0x0f, len))
+}
+
+/// A typed int, e.g. a FORMAT key or a long length.
+fn read_int(src: &mut &[u8]) -> io::Result<Option<i32>> {
+    let (ty, len) = read_type(src)?;
+    if len != 1 {
+        return Err(invalid_data("expected a single BCF int"));
+    }
+    Ok(ints(ty, take(src, bytes(ty, 1)?)?)?.next().flatten())
+}
+
+/// A value of an int type.
+#[derive(Clone, Copy)]
+enum Int {
+    Value(i32),
+    Missing,
+    EndOfVector,
+}
+
+/// The ints of some BCF data, with the missing and end of vector sentinels of its type.
+fn ints(ty: u8, data: &[u8]) -> io::Result<impl Iterator<Item = Option<i32>> + '_> {
+    Ok(raw_ints(ty, data)?.map_while(|n| match n {
+        Int::Value(n) => Some(Some(n)),
+        Int::Missing => Some(None),
+        Int::EndOfVector => None,
+    }))
+}
+
+fn raw_ints(ty: u8, data: &[u8]) -> io::Result<impl Iterator<Item = Int> + '_> {
+    let size = match ty {
+        1..=3 => size_of(ty)?,
+        ty => return Err(invalid_data(&format!("expected a BCF int, got type {ty}"))),
+    };
+
+    Ok(data.chunks_exact(size).map(move |b| {
+        let (n, missing) = match *b {
+            [a] => (i32::from(a as i8), i32::from(i8::MIN)),
+            [a, b] => (i32::from(i16::from_le_bytes([a, b])), i32::from(i16::MIN)),
+            [a, b, c, d] => (i32::from_le_bytes([a, b, c, d]), i32::MIN),
+            _ => unreachable!("ints are 1, 2 or 4 bytes"),
+        };
+        if n == missing {
+            Int::Missing
+        } else if n == missing + 1 {
+            Int::EndOfVector
+        } else {
+            Int::Value(n)
+        }
+    }))
+}
+
+/// The floats of some BCF data, up to the end of the vector.
+fn floats(data: &[u8]) -> impl Iterator<Item = Option<f32>> + '_ {
+    const MISSING: u32 = 0x7f80_0001;
+    const END_OF_VECTOR: u32 = 0x7f80_0002;
+
+    data.chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .take_while(|&bits| bits != END_OF_VECTOR)
+        .map(|bits| (bits != MISSING).then(|| f32::from_bits(bits)))
+}
+
+/// A string, up to any NUL padding.
+fn string(data: &[u8]) -> io::Result<&str> {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    str::from_utf8(&data[..end]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// A GT value: each allele is stored as `(index + 1) << 1`, with the low bit set if it's
+/// phased, and 0 for a missing allele.
+fn genotype(ty: u8, data: &[u8]) -> io::Result<Vec<(Option<usize>, bool)>> {
+    Ok(raw_ints(ty, data)?
+        .map_while(|n| match n {
+            Int::Value(n) => Some(n),
+            Int::Missing => Some(0),
+            Int::EndOfVector => None,
+        })
+        .map(|n| {
+            let allele = usize::try_from((n >> 1) - 1).ok();
+            (allele, n & 1 == 1)
+        })
+        .collect())
+}
+
+/// A FORMAT value other than GT. A single missing value is `None`, as it is in VCF.
+fn sample_value(ty: u8, len: usize, data: &[u8]) -> io::Result<Option<Value>> {
+    let value = match (ty, len) {
+        (0, _) => None,
+        (7, _) => match string(data)? {
+            "" | "." => None,
+            s => Some(Value::from(s)),
+        },
+        (5, 1) => floats(data).next().flatten().map(Value::from),
+        (5, _) => match floats(data).collect::<Vec<_>>()[..] {
+            [None] => None,
+            ref vals => Some(Value::from(vals.to_vec())),
+        },
+        (_, 1) => ints(ty, data)?.next().flatten().map(Value::from),
+        _ => match ints(ty, data)?.collect::<Vec<_>>()[..] {
+            [None] => None,
+            ref vals => Some(Value::from(vals.to_vec())),
+        },
+    };
+    Ok(value)
+}
@@ -2,7 +2,7 @@ use noodles::bed;
 use nu_plugin::EvaluatedCall;
 use nu_protocol::{LabeledError, Record, Value};
 
-use super::SpanExt;
+use super::{decompress, stream_records, Compression, Input, SpanExt, ValueStream};
 
 /// BED reader type
 const BED_COLUMN_NUMBER: u8 = 3;
@@ -17,37 +17,36 @@ pub const BED_COLUMNS: &[&str] = &[
     "chromEnd",
 ];
 
-pub fn from_bed_inner(call: &EvaluatedCall, input: &Value) -> Result<Vec<Value>, LabeledError> {
-    let bytes = match input.as_binary() {
-        Ok(b) => b,
-        Err(e) => {
-            return Err(LabeledError::new(format!("cause of failure: {}", e))
-                .with_label("Value conversion to binary failed.", call.head))
-        }
-    };
-
-    let mut reader = bed::Reader::new(bytes);
-
-    let mut records = Vec::new();
-
-    for result in reader.records::<BED_COLUMN_NUMBER>() {
-        let record = result.map_err(|e| {
-            LabeledError::new(format!("{e}"))
-                .with_label("Failed reading a record in the BED file", call.head)
-        })?;
-
-        let mut row = Vec::new();
-
-        row.push(call.head.with_string(record.reference_sequence_name()));
-        let start: usize = record.start_position().into();
-        row.push(Value::int(start as i64, call.head));
-        let end: usize = record.end_position().into();
-        row.push(Value::int(end as i64, call.head));
-
-        let record_inner = Record::from_iter(BED_COLUMNS.iter().map(|e| e.to_string()).zip(row));
-
-        records.push(Value::record(record_inner, call.head))
-    }
-
-    Ok(records)
+/// Parse a BED file into a stream of nushell records.
+pub fn from_bed_inner(
+    call: &EvaluatedCall,
+    input: Input,
+    gz: &Compression,
+) -> Result<ValueStream, LabeledError> {
+    let mut reader = bed::Reader::new(decompress(input, gz));
+
+    let span = call.head;
+    let records = std::iter::from_fn(move || reader.records::<BED_COLUMN_NUMBER>().next()).map(
+        move |result| {
+            let record = result.map_err(|e| {
+                LabeledError::new(format!("{e}"))
+                    .with_label("Failed reading a record in the BED file", span)
+            })?;
+
+            let mut row = Vec::new();
+
+            row.push(span.with_string(record.reference_sequence_name()));
+            let start: usize = record.start_position().into();
+            row.push(Value::int(start as i64, span));
+            let end: usize = record.end_position().into();
+            row.push(Value::int(end as i64, span));
+
+            let record_inner =
+                Record::from_iter(BED_COLUMNS.iter().map(|e| e.to_string()).zip(row));
+
+            Ok(Value::record(record_inner, span))
+        },
+    );
+
+    Ok(stream_records(records, span))
 }
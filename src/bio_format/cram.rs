@@ -1,27 +1,31 @@
 /// The CRAM format
-use noodles::cram;
-use noodles::sam;
+use std::any::Any;
+use std::io::{self, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+
+use noodles::sam::{self, record::data::field::Tag};
+use noodles::{cram, fasta};
 use nu_plugin::EvaluatedCall;
-use nu_protocol::{record, LabeledError, Record, Value};
-
-use crate::bio_format::bam::{create_record_values, parse_header, BAM_COLUMNS};
-// TODO: also allow the reference to be passed, so we can view the alignment sequences?
-
-/// Parse a CRAM file into a nushell structure.
-pub fn from_cram_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val,
-        other => {
-            return Err(LabeledError::new(format!(
-                "requires binary input, got {}",
-                other.get_type()
-            ))
-            .with_label("Input should be binary.", call.head))
-        }
-    };
+use nu_protocol::{LabeledError, Span, Value};
+
+use crate::bio_format::bam::{alignment_to_nuon, parse_header, tags_from};
+use crate::bio_format::fasta::Reference;
+use crate::bio_format::{stream_records, Input, ValueStream};
+/// How many decoded records can be waiting to be read from the decoding thread.
+const RECORD_BUFFER: usize = 1024;
 
-    let mut reader = cram::Reader::new(stream.as_slice());
+/// Parse a CRAM file into its header and a stream of records.
+///
+/// Most CRAM files only store the differences from the reference genome, so without
+/// a `reference`, the sequences and quality scores of their records are empty.
+pub fn from_cram_inner(
+    call: &EvaluatedCall,
+    input: Input,
+    reference: Option<Reference>,
+) -> Result<(Value, ValueStream), LabeledError> {
+    let mut reader = cram::Reader::new(input);
 
     match reader.read_file_definition() {
         Ok(_) => (),
@@ -40,32 +44,118 @@ pub fn from_cram_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, Lab
     };
 
     let header_nuon = parse_header(call, &header);
+    let tags = tags_from(call)?;
 
-    let mut value_records = Vec::new();
+    let span = call.head;
+    // the reader holds a reference sequence repository which cannot be sent between
+    // threads, so decoding happens on its own thread, picking up after the header.
+    let inner = reader.into_inner();
+    let (tx, rx) = sync_channel(RECORD_BUFFER);
 
-    while let Some(container) = reader.read_data_container().unwrap() {
-        for slice in container.slices() {
-            // FIXME: if these unwraps in this section are converted to match LabeledErrors as
-            // above, some of my test crams hang indefinitely in parsing(?)
-            let records = slice.records(container.compression_header()).unwrap();
+    thread::spawn(move || {
+        let mut reader = cram::Reader::new(inner);
+        let repository = reference.map(fasta::Repository::new);
 
-            for r in records {
-                let r = r.try_into_alignment_record(&header).unwrap();
-                let vec_vals = create_record_values(call, r);
+        // the stream ends when `tx` is dropped, so a panic here would look like the end of a
+        // shorter, valid file. Panics are sent on as errors instead, and an error ends the
+        // stream, so decoding stops after sending it on.
+        let decoded = panic::catch_unwind(AssertUnwindSafe(|| {
+            decode_records(&mut reader, &header, &tags, repository.as_ref(), span, &tx)
+        }))
+        .unwrap_or_else(|payload| {
+            Err(LabeledError::new(format!(
+                "cause of failure: {}",
+                panic_message(payload.as_ref())
+            ))
+            .with_label("CRAM decoding failed.", span))
+        });
+
+        if let Err(e) = decoded {
+            let _ = tx.send(Err(e));
+        }
+    });
 
-                let records_inner =
-                    Record::from_iter(BAM_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+    Ok((header_nuon, stream_records(rx.into_iter(), span)))
+}
+
+/// The message a panic was raised with, if it has one.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("the CRAM decoder panicked")
+}
+
+/// Decode the records of each data container in turn, sending them on as nushell records.
+///
+/// Errors name the container and slice they happened in. If the receiving end hangs up,
+/// nobody wants the rest, so decoding stops early.
+fn decode_records<R: Read>(
+    reader: &mut cram::Reader<R>,
+    header: &sam::Header,
+    tags: &[Tag],
+    repository: Option<&fasta::Repository>,
+    span: Span,
+    tx: &SyncSender<Result<Value, LabeledError>>,
+) -> Result<(), LabeledError> {
+    let cram_error = |e: io::Error, label: String| {
+        LabeledError::new(format!("cause of failure: {}", e)).with_label(label, span)
+    };
+
+    for container_index in 0.. {
+        let container = match reader.read_data_container() {
+            Ok(Some(container)) => container,
+            Ok(None) => break,
+            Err(e) => {
+                return Err(cram_error(
+                    e,
+                    format!("Could not read data container {container_index}."),
+                ))
+            }
+        };
+        let compression_header = container.compression_header();
+
+        for (slice_index, slice) in container.slices().iter().enumerate() {
+            let location = format!("slice {slice_index} of data container {container_index}");
+
+            let mut records = slice
+                .records(compression_header)
+                .map_err(|e| cram_error(e, format!("Could not read the records in {location}.")))?;
+
+            if let Some(repository) = repository {
+                // noodles indexes into the reference with positions from the records, which
+                // panics rather than erroring if a corrupt record points outside of it.
+                let resolved = panic::catch_unwind(AssertUnwindSafe(|| {
+                    slice.resolve_records(repository, header, compression_header, &mut records)
+                }))
+                .unwrap_or_else(|_| {
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "record positions are outside of the reference sequence",
+                    ))
+                });
+
+                resolved.map_err(|e| {
+                    cram_error(
+                        e,
+                        format!("Could not restore the records in {location} with the reference."),
+                    )
+                })?;
+            }
+
+            for r in records {
+                let r = r.try_into_alignment_record(header).map_err(|e| {
+                    cram_error(e, format!("Could not convert a record in {location}."))
+                })?;
+                let value = alignment_to_nuon(span, header, tags, r)?;
 
-                value_records.push(Value::record(records_inner, call.head))
+                if tx.send(Ok(value)).is_err() {
+                    return Ok(());
+                }
             }
         }
     }
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head)
-        },
-        call.head,
-    ))
+    Ok(())
 }
@@ -1,7 +1,13 @@
+use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 
+use noodles::core::Region;
 use noodles::fasta::{
+    fai,
+    io::BufReadSeek,
     record::{Definition as FastaDefinition, Record as FastaRecord, Sequence},
+    repository::Adapter,
     Writer as FastaWriter,
 };
 use noodles::fastq::{
@@ -10,84 +16,80 @@ use noodles::fastq::{
 };
 use noodles::{bgzf, fasta, fastq};
 use nu_plugin::EvaluatedCall;
-use nu_protocol::{LabeledError, Value};
+use nu_protocol::{record, LabeledError, Span, Value};
 
-use crate::bio_format::{Compression, SpanExt};
+use crate::bio_format::{
+    stream_records, with_appended_extension, Compression, Input, SpanExt, ValueStream,
+};
 
 /// Compression status of a fastq reader.
-enum FastqReader<'a> {
-    Uncompressed(Box<fastq::Reader<&'a [u8]>>),
-    Compressed(Box<fastq::Reader<BufReader<bgzf::Reader<&'a [u8]>>>>),
+enum FastqReader {
+    Uncompressed(Box<fastq::Reader<Input>>),
+    Compressed(Box<fastq::Reader<BufReader<bgzf::Reader<Input>>>>),
 }
 
 /// Compression status of a fasta reader.
-enum FastaReader<'a> {
-    Uncompressed(Box<fasta::Reader<&'a [u8]>>),
-    Compressed(fasta::Reader<Box<bgzf::Reader<&'a [u8]>>>),
+enum FastaReader {
+    Uncompressed(Box<fasta::Reader<Input>>),
+    Compressed(fasta::Reader<Box<bgzf::Reader<Input>>>),
 }
 
-/// Iterate over the records of a reader that implements [`BufRead`].
-fn iterate_fastq_records<R: BufRead>(
+/// Lazily iterate over the records of a reader that implements [`BufRead`].
+fn iterate_fastq_records<R: BufRead + Send + 'static>(
     mut reader: fastq::Reader<R>,
-    call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
+    span: Span,
     description: bool,
     quality_scores: bool,
     cols: Vec<String>,
-) -> Result<(), LabeledError> {
+) -> ValueStream {
     // iterate over the records.
-    for record in reader.records() {
+    let records = std::iter::from_fn(move || reader.records().next()).map(move |record| {
         let r = record.map_err(|e| {
             LabeledError::new(format!("cause of failure: {}", e))
-                .with_label("Record reading failed.", call.head)
+                .with_label("Record reading failed.", span)
         })?;
 
         let mut vec_vals = Vec::new();
-        vec_vals.push(call.head.with_string_from_utf8(r.name()));
+        vec_vals.push(span.with_string_from_utf8(r.name())?);
 
         if description {
-            vec_vals.push(call.head.with_string_from_utf8(r.description()));
+            vec_vals.push(span.with_string_from_utf8(r.description())?);
         }
 
         if quality_scores {
-            vec_vals.push(call.head.with_string_from_utf8(r.quality_scores()));
+            vec_vals.push(span.with_string_from_utf8(r.quality_scores())?);
         }
 
-        vec_vals.push(call.head.with_string_from_utf8(r.sequence()));
+        vec_vals.push(span.with_string_from_utf8(r.sequence())?);
 
         let mut tmp_record = nu_protocol::Record::new();
-        for (col, val) in cols.clone().iter().zip(vec_vals) {
+        for (col, val) in cols.iter().zip(vec_vals) {
             tmp_record.push(col, val);
         }
-        value_records.push(Value::record(tmp_record, call.head))
-    }
+        Ok(Value::record(tmp_record, span))
+    });
 
-    Ok(())
+    stream_records(records, span)
 }
 
 pub fn from_fastq_inner(
     call: &EvaluatedCall,
-    input: &Value,
+    input: Input,
     gz: &Compression,
-) -> Result<Vec<Value>, LabeledError> {
+) -> Result<ValueStream, LabeledError> {
     // parse description flag.
-    let description = call.has_flag("description");
-    let quality_scores = call.has_flag("quality-scores");
-
-    let bytes = input.as_binary().map_err(|e| {
-        LabeledError::new(format!("cause of failure: {}", e))
-            .with_label("Value conversion to binary failed.", call.head)
-    })?;
+    let description = call.has_flag("description")?;
+    let quality_scores = call.has_flag("quality-scores")?;
 
     let reader = match gz {
-        Compression::Uncompressed => FastqReader::Uncompressed(Box::new(fastq::Reader::new(bytes))),
+        Compression::Uncompressed => FastqReader::Uncompressed(Box::new(fastq::Reader::new(input))),
         Compression::Gzipped => {
-            let gz = bgzf::Reader::new(bytes);
+            let gz = bgzf::Reader::new(input);
             FastqReader::Compressed(Box::new(fastq::Reader::new(BufReader::new(gz))))
         }
     };
 
-    let cols = match (description.is_ok(), quality_scores.is_ok()) {
+    let cols = match (description, quality_scores) {
         (false, false) => vec!["id".to_string(), "sequence".to_string()],
         (true, false) => vec![
             "id".to_string(),
@@ -107,83 +109,68 @@ pub fn from_fastq_inner(
         ],
     };
 
-    let mut value_records = Vec::new();
-
-    match reader {
-        FastqReader::Uncompressed(u) => iterate_fastq_records(
-            *u,
-            call,
-            &mut value_records,
-            description.is_ok(),
-            quality_scores.is_ok(),
-            cols,
-        )?,
-        FastqReader::Compressed(c) => iterate_fastq_records(
-            *c,
-            call,
-            &mut value_records,
-            description.is_ok(),
-            quality_scores.is_ok(),
-            cols,
-        )?,
-    };
-
-    Ok(value_records)
+    Ok(match reader {
+        FastqReader::Uncompressed(u) => {
+            iterate_fastq_records(*u, call.head, description, quality_scores, cols)
+        }
+        FastqReader::Compressed(c) => {
+            iterate_fastq_records(*c, call.head, description, quality_scores, cols)
+        }
+    })
 }
 
-fn iterate_fasta_records<R: BufRead>(
+/// Lazily iterate over the records of a reader that implements [`BufRead`].
+fn iterate_fasta_records<R: BufRead + Send + 'static>(
     mut reader: fasta::Reader<R>,
-    call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
+    span: Span,
     description: bool,
     cols: Vec<String>,
-) -> Result<(), LabeledError> {
+) -> ValueStream {
     // iterate over the records
-    for record in reader.records() {
+    let records = std::iter::from_fn(move || reader.records().next()).map(move |record| {
         let r = record.map_err(|e| {
             LabeledError::new(format!("cause of failure: {}", e))
-                .with_label("Record reading failed.", call.head)
+                .with_label("Record reading failed.", span)
         })?;
 
         let mut vec_vals = Vec::new();
 
-        vec_vals.push(call.head.with_string(r.name()));
+        vec_vals.push(span.with_string(r.name()));
 
         if description {
-            vec_vals.push(call.head.with_string_or(r.description(), ""));
+            vec_vals.push(span.with_string_or(r.description(), ""));
         }
 
-        vec_vals.push(call.head.with_string_from_utf8(r.sequence().as_ref()));
+        vec_vals.push(span.with_string_from_utf8(r.sequence().as_ref())?);
 
         let mut tmp_record = nu_protocol::Record::new();
-        for (col, val) in cols.clone().iter().zip(vec_vals) {
+        for (col, val) in cols.iter().zip(vec_vals) {
             tmp_record.push(col, val);
         }
-        value_records.push(Value::record(tmp_record, call.head))
-    }
-    Ok(())
+        Ok(Value::record(tmp_record, span))
+    });
+
+    stream_records(records, span)
 }
 
-/// Parse a fasta file into a nushell structure.
+/// Parse a fasta file into a stream of nushell records.
 pub fn from_fasta_inner(
     call: &EvaluatedCall,
-    input: &Value,
+    input: Input,
     gz: &Compression,
-) -> Result<Vec<Value>, LabeledError> {
+) -> Result<ValueStream, LabeledError> {
     // parse description flag.
-    let description = call.has_flag("description");
-
-    let bytes = input.as_binary()?;
+    let description = call.has_flag("description")?;
 
     let reader = match gz {
-        Compression::Uncompressed => FastaReader::Uncompressed(Box::new(fasta::Reader::new(bytes))),
+        Compression::Uncompressed => FastaReader::Uncompressed(Box::new(fasta::Reader::new(input))),
         Compression::Gzipped => {
-            let gz = Box::new(bgzf::Reader::new(bytes));
+            let gz = Box::new(bgzf::Reader::new(input));
             FastaReader::Compressed(fasta::Reader::new(gz))
         }
     };
 
-    let cols = if description.is_ok() {
+    let cols = if description {
         vec![
             "id".to_string(),
             "description".to_string(),
@@ -193,18 +180,253 @@ pub fn from_fasta_inner(
         vec!["id".to_string(), "sequence".to_string()]
     };
 
-    let mut value_records = Vec::new();
+    Ok(match reader {
+        FastaReader::Uncompressed(u) => iterate_fasta_records(*u, call.head, description, cols),
+        FastaReader::Compressed(c) => iterate_fasta_records(c, call.head, description, cols),
+    })
+}
 
-    match reader {
-        FastaReader::Uncompressed(u) => {
-            iterate_fasta_records(*u, call, &mut value_records, description.is_ok(), cols)?
-        }
-        FastaReader::Compressed(c) => {
-            iterate_fasta_records(c, call, &mut value_records, description.is_ok(), cols)?
-        }
+/// A plain or bgzipped FASTA file which can be read with its index.
+pub type IndexedFasta = fasta::IndexedReader<Box<dyn BufReadSeek + Send>>;
+
+/// Bgzipped FASTA files need their `.gzi` index as well as the `.fai`.
+fn is_bgzipped(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("gz" | "bgz")
+    )
+}
+
+/// Read the `.fai` index of a FASTA file, either the one given or the one next to it.
+///
+/// If there is no index and `build` is set, it is created next to the FASTA (or at
+/// the given path) for next time.
+pub fn read_fai(
+    call: &EvaluatedCall,
+    path: &Path,
+    index: Option<PathBuf>,
+    build: bool,
+) -> Result<fai::Index, LabeledError> {
+    let index_path = index.unwrap_or_else(|| with_appended_extension(path, "fai"));
+
+    if index_path.exists() {
+        return fai::read(&index_path).map_err(|e| {
+            LabeledError::new(format!("cause of failure: {}", e)).with_label(
+                format!("Could not read the index {}.", index_path.display()),
+                call.head,
+            )
+        });
+    }
+
+    if !build {
+        return Err(
+            LabeledError::new(format!("no .fai index found for {}", path.display()))
+                .with_label("Indexed FASTA access needs an index.", call.head)
+                .with_help("create one with --build, or pass it with --index"),
+        );
+    }
+
+    if is_bgzipped(path) {
+        return Err(LabeledError::new(format!(
+            "cannot build the index of the bgzipped FASTA {}",
+            path.display()
+        ))
+        .with_label("Only plain FASTA files can be indexed.", call.head)
+        .with_help("use `samtools faidx`, which also creates the .gzi index"));
+    }
+
+    let index = fasta::index(path).map_err(|e| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label(format!("Could not index {}.", path.display()), call.head)
+    })?;
+
+    File::create(&index_path)
+        .map(fai::Writer::new)
+        .and_then(|mut writer| writer.write_index(&index))
+        .map_err(|e| {
+            LabeledError::new(format!("cause of failure: {}", e)).with_label(
+                format!("Could not write the index {}.", index_path.display()),
+                call.head,
+            )
+        })?;
+
+    Ok(index)
+}
+
+/// Open a FASTA file for reading with its index.
+pub fn open_indexed_fasta(
+    call: &EvaluatedCall,
+    path: &Path,
+    index: fai::Index,
+) -> Result<IndexedFasta, LabeledError> {
+    let open_error = |e: std::io::Error, what: &Path| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label(format!("Could not open {}.", what.display()), call.head)
     };
 
-    Ok(value_records)
+    let reader: Box<dyn BufReadSeek + Send> = if is_bgzipped(path) {
+        let gzi_path = with_appended_extension(path, "gzi");
+        let gzi = bgzf::gzi::read(&gzi_path).map_err(|e| {
+            open_error(e, &gzi_path).with_help("create the .gzi index with `samtools faidx`")
+        })?;
+
+        Box::new(
+            bgzf::indexed_reader::Builder::default()
+                .set_index(gzi)
+                .build_from_path(path)
+                .map_err(|e| open_error(e, path))?,
+        )
+    } else {
+        Box::new(BufReader::new(
+            File::open(path).map_err(|e| open_error(e, path))?,
+        ))
+    };
+
+    Ok(fasta::IndexedReader::new(reader, index))
+}
+
+/// A reference genome, e.g. to restore the bases of CRAM records.
+pub enum Reference {
+    /// Sequences are read as they are needed, with the `.fai` index.
+    Indexed(IndexedFasta),
+    /// Without an index, the whole FASTA is read up front.
+    Records(Vec<FastaRecord>),
+}
+
+impl Adapter for Reference {
+    fn get(&mut self, name: &str) -> Option<std::io::Result<FastaRecord>> {
+        // a missing sequence is an error rather than `None`, which noodles would panic on.
+        Some(match self {
+            Reference::Indexed(reader) => reader.query(&Region::new(name, ..)),
+            Reference::Records(records) => records
+                .iter()
+                .find(|r| r.name() == name)
+                .cloned()
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("reference sequence {name} is not in the reference"),
+                    )
+                }),
+        })
+    }
+}
+
+/// Read a reference genome, using its `.fai` index if there is one next to it.
+pub fn read_reference(call: &EvaluatedCall, path: &Path) -> Result<Reference, LabeledError> {
+    if with_appended_extension(path, "fai").exists() {
+        let index = read_fai(call, path, None, false)?;
+        return open_indexed_fasta(call, path, index).map(Reference::Indexed);
+    }
+
+    let read_error = |e: std::io::Error| {
+        LabeledError::new(format!("cause of failure: {}", e)).with_label(
+            format!("Could not read the reference {}.", path.display()),
+            call.head,
+        )
+    };
+
+    let file = BufReader::new(File::open(path).map_err(read_error)?);
+    let records = if is_bgzipped(path) {
+        fasta::Reader::new(BufReader::new(bgzf::Reader::new(file)))
+            .records()
+            .collect::<Result<_, _>>()
+    } else {
+        fasta::Reader::new(file).records().collect::<Result<_, _>>()
+    };
+
+    records.map(Reference::Records).map_err(read_error)
+}
+
+/// The records of a `.fai` index as a table.
+pub fn fai_to_nuon(index: &fai::Index, span: Span) -> Value {
+    let records = index
+        .iter()
+        .map(|r| {
+            Value::record(
+                record! {
+                    "name" => span.with_string(r.name()),
+                    "length" => Value::int(r.length() as i64, span),
+                    "offset" => Value::int(r.offset() as i64, span),
+                    "line_bases" => Value::int(r.line_bases() as i64, span),
+                    "line_width" => Value::int(r.line_width() as i64, span),
+                },
+                span,
+            )
+        })
+        .collect();
+
+    Value::list(records, span)
+}
+
+/// Reverse complement a sequence, keeping soft-masked (lower case) bases lower case.
+///
+/// Bases without a complement are left as they are.
+fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence
+        .iter()
+        .rev()
+        .map(|&base| {
+            let complement = match base.to_ascii_uppercase() {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                b'T' | b'U' => b'A',
+                b'M' => b'K',
+                b'K' => b'M',
+                b'R' => b'Y',
+                b'Y' => b'R',
+                b'B' => b'V',
+                b'V' => b'B',
+                b'D' => b'H',
+                b'H' => b'D',
+                other => other,
+            };
+
+            if base.is_ascii_lowercase() {
+                complement.to_ascii_lowercase()
+            } else {
+                complement
+            }
+        })
+        .collect()
+}
+
+/// Extract the subsequences of regions from an indexed FASTA file, as a stream of
+/// records with the same columns as `from fasta`.
+pub fn faidx_inner(
+    call: &EvaluatedCall,
+    mut reader: IndexedFasta,
+    regions: Vec<Region>,
+    reverse: bool,
+) -> ValueStream {
+    let span = call.head;
+
+    let records = regions.into_iter().map(move |region| {
+        let r = reader.query(&region).map_err(|e| {
+            LabeledError::new(format!("cause of failure: {}", e))
+                .with_label(format!("Could not extract region {region}."), span)
+        })?;
+
+        let (id, sequence) = if reverse {
+            (
+                format!("{region}/rc"),
+                reverse_complement(r.sequence().as_ref()),
+            )
+        } else {
+            (region.to_string(), r.sequence().as_ref().to_vec())
+        };
+
+        Ok(Value::record(
+            record! {
+                "id" => span.with_string(id),
+                "sequence" => span.with_string_from_utf8(&sequence)?,
+            },
+            span,
+        ))
+    });
+
+    stream_records(records, span)
 }
 
 /// Go from a parsed nuon fasta structure to a string to stdout
@@ -230,10 +452,10 @@ pub fn nuon_to_fasta(call: &EvaluatedCall, input: &Value) -> Result<Value, Label
                 .and_then(|e| e.as_str().ok())
                 .map(|v| v.to_string());
 
-            let sequence = vals.last().unwrap().as_str()?;
+            let sequence = vals.next_back().unwrap().as_str()?;
 
             let fa_def = FastaDefinition::new(id, description);
-            let fa_seq = Sequence::from(sequence.as_bytes().iter().cloned().collect::<Vec<_>>());
+            let fa_seq = Sequence::from(sequence.as_bytes().to_vec());
 
             out.write_record(&FastaRecord::new(fa_def.clone(), fa_seq))
                 .map_err(|err| {
@@ -264,9 +486,8 @@ pub fn nuon_to_fastq(call: &EvaluatedCall, input: &Value) -> Result<Value, Label
                 let first_inner = e.as_record()?;
                 let mut cols = first_inner.columns();
                 (
-                    cols.position(|c| *c == String::from("description"))
-                        .is_some(),
-                    cols.position(|c| *c == String::from("quality_scores")),
+                    cols.position(|c| c == "description").is_some(),
+                    cols.position(|c| c == "quality_scores"),
                 )
             }
             None => {
@@ -323,7 +544,7 @@ pub fn nuon_to_fastq(call: &EvaluatedCall, input: &Value) -> Result<Value, Label
             // };
 
             let sequence = vals
-                .last()
+                .next_back()
                 .and_then(|v| v.as_str().ok().map(|str| str.to_string()))
                 .unwrap_or_default();
 
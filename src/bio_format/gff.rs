@@ -1,9 +1,16 @@
 /// The GFF format
-use noodles::gff;
+use noodles::{
+    fasta,
+    gff::{
+        self,
+        record::{attributes::field::Value as AttributeValue, Phase, Strand},
+    },
+    gtf,
+};
 use nu_plugin::EvaluatedCall;
-use nu_protocol::{LabeledError, Record, Value};
+use nu_protocol::{record, LabeledError, Record, Span, Value};
 
-use super::SpanExt;
+use super::{decompress, float_to_nuon, stream_records, Compression, Input, SpanExt, ValueStream};
 
 /// The GFF3 headers
 const GFF_COLUMNS: &[&str] = &[
@@ -18,52 +25,334 @@ const GFF_COLUMNS: &[&str] = &[
     "attributes",
 ];
 
-/// Add a GFF record
-fn add_record(call: &EvaluatedCall, r: gff::Record, vec_vals: &mut Vec<Value>) {
+/// Parse the attributes passed to `--attributes`, either comma separated (e.g. `ID,Parent`) or
+/// as a list.
+pub fn attributes_from(call: &EvaluatedCall) -> Result<Vec<String>, LabeledError> {
+    let Some(value) = call.get_flag_value("attributes") else {
+        return Ok(Vec::new());
+    };
+
+    let attributes = match &value {
+        Value::List { vals, .. } => vals.iter().map(Value::as_str).collect::<Result<_, _>>()?,
+        other => other.as_str()?.split(',').collect::<Vec<_>>(),
+    };
+
+    Ok(attributes
+        .into_iter()
+        .map(|key| key.trim().to_string())
+        .collect())
+}
+
+/// GFF3 attributes as a record, with keys and values percent-decoded.
+///
+/// Attributes with more than one value (e.g. `Parent=mRNA1,mRNA2`) are lists.
+fn attributes_to_nuon(attributes: &gff::record::Attributes, span: Span) -> Value {
+    let record = attributes
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                AttributeValue::String(s) => span.with_string(s),
+                AttributeValue::Array(vals) => {
+                    Value::list(vals.iter().map(|v| span.with_string(v)).collect(), span)
+                }
+            };
+            (key.clone(), value)
+        })
+        .collect();
+
+    Value::record(record, span)
+}
+
+/// A row of the GFF columns, with any `attributes` asked for added as columns of their own,
+/// `null` if the record doesn't have them.
+fn columns_to_nuon(values: Vec<Value>, attributes: &[String], span: Span) -> Value {
+    let mut record = Record::from_iter(GFF_COLUMNS.iter().map(|e| e.to_string()).zip(values));
+
+    let promoted: Vec<Value> = match record.get("attributes") {
+        Some(Value::Record { val, .. }) => attributes
+            .iter()
+            .map(|key| val.get(key).cloned().unwrap_or(Value::nothing(span)))
+            .collect(),
+        _ => Vec::new(),
+    };
+    for (key, value) in attributes.iter().zip(promoted) {
+        record.push(key.clone(), value);
+    }
+
+    Value::record(record, span)
+}
+
+/// The phase of a CDS feature, as an int.
+fn phase_to_nuon(phase: Phase, span: Span) -> Value {
+    let phase = match phase {
+        Phase::Zero => 0,
+        Phase::One => 1,
+        Phase::Two => 2,
+    };
+    Value::int(phase, span)
+}
+
+/// The strand of a feature, `+` or `-`, or `null` if it's unstranded (`.`) or unknown (`?`).
+fn strand_to_nuon(strand: Strand, span: Span) -> Value {
+    match strand {
+        Strand::Forward | Strand::Reverse => span.with_string(strand),
+        Strand::None | Strand::Unknown => Value::nothing(span),
+    }
+}
+
+/// A GFF record as a nushell record.
+///
+/// The score is a float and the phase an int, and the strand is `+` or `-`, all `null` if
+/// missing.
+fn record_to_nuon(r: gff::Record, attributes: &[String], span: Span) -> Value {
     let start = usize::from(r.start());
     let end = usize::from(r.end());
 
-    let values_to_extend: Vec<Value> = vec![
-        call.head.with_string(r.reference_sequence_name()),
-        call.head.with_string(r.source()),
-        call.head.with_string(r.ty()),
-        Value::int(start as i64, call.head),
-        Value::int(end as i64, call.head),
-        call.head.with_string_or(r.score(), ""),
-        call.head.with_string(r.strand()),
-        call.head.with_string_or(r.phase(), ""),
-        call.head.with_string(r.attributes()),
+    let values = vec![
+        span.with_string(r.reference_sequence_name()),
+        span.with_string(r.source()),
+        span.with_string(r.ty()),
+        Value::int(start as i64, span),
+        Value::int(end as i64, span),
+        r.score()
+            .map_or(Value::nothing(span), |score| float_to_nuon(score, span)),
+        strand_to_nuon(r.strand(), span),
+        r.phase()
+            .map_or(Value::nothing(span), |phase| phase_to_nuon(phase, span)),
+        attributes_to_nuon(r.attributes(), span),
     ];
 
-    vec_vals.extend_from_slice(&values_to_extend);
+    columns_to_nuon(values, attributes, span)
+}
+
+/// Parse a GFF file into a stream of its features, stopping at any `##FASTA` section.
+pub fn stream_gff_inner(
+    call: &EvaluatedCall,
+    input: Input,
+    gz: &Compression,
+) -> Result<ValueStream, LabeledError> {
+    let mut reader = gff::Reader::new(decompress(input, gz));
+    let attributes = attributes_from(call)?;
+
+    let span = call.head;
+    let records = std::iter::from_fn(move || reader.records().next()).map(move |record| {
+        record
+            .map(|r| record_to_nuon(r, &attributes, span))
+            .map_err(|e| {
+                LabeledError::new(format!("cause of failure: {}", e))
+                    .with_label("Record reading failed.", span)
+            })
+    });
+
+    Ok(stream_records(records, span))
+}
+
+/// Add a value to a record, making a list of the values if the key is repeated.
+fn push_repeated(record: &mut Record, key: &str, value: Value, span: Span) {
+    match record.get_mut(key) {
+        Some(Value::List { vals, .. }) => vals.push(value),
+        Some(existing) => {
+            let first = std::mem::replace(existing, Value::nothing(span));
+            *existing = Value::list(vec![first, value], span);
+        }
+        None => record.push(key, value),
+    }
+}
+
+/// The GFF3 directives, with a column for each of the standard ones (`null` if missing),
+/// and any others in `other`, as they'd be in the header of a VCF.
+///
+/// `###` only marks where forward references between features are resolved, so isn't kept.
+#[derive(Default)]
+struct Directives {
+    gff_version: Option<String>,
+    sequence_region: Record,
+    species: Option<String>,
+    genome_build: Option<Value>,
+    feature_ontology: Option<String>,
+    attribute_ontology: Option<String>,
+    source_ontology: Option<String>,
+    other: Record,
 }
 
-/// Parse a fasta file into a nushell structure.
-pub fn from_gff_inner(call: &EvaluatedCall, input: &Value) -> Result<Vec<Value>, LabeledError> {
-    // match on file type
-    let stream = input.as_binary()?;
+impl Directives {
+    fn add(&mut self, directive: gff::Directive, span: Span) {
+        use gff::Directive;
 
-    let mut reader = gff::Reader::new(stream);
+        match directive {
+            Directive::GffVersion(version) => self.gff_version = Some(version.to_string()),
+            Directive::SequenceRegion(region) => {
+                // a repeated region replaces the earlier one, rather than adding a second key
+                self.sequence_region.insert(
+                    region.reference_sequence_name(),
+                    Value::record(
+                        record! {
+                            "start" => Value::int(i64::from(region.start()), span),
+                            "end" => Value::int(i64::from(region.end()), span),
+                        },
+                        span,
+                    ),
+                );
+            }
+            Directive::Species(uri) => self.species = Some(uri),
+            Directive::GenomeBuild(build) => {
+                self.genome_build = Some(Value::record(
+                    record! {
+                        "source" => span.with_string(build.source()),
+                        "name" => span.with_string(build.name()),
+                    },
+                    span,
+                ))
+            }
+            Directive::FeatureOntology(uri) => self.feature_ontology = Some(uri),
+            Directive::AttributeOntology(uri) => self.attribute_ontology = Some(uri),
+            Directive::SourceOntology(uri) => self.source_ontology = Some(uri),
+            Directive::Other(name, value) => {
+                let value = value.map_or(Value::nothing(span), |v| span.with_string(v));
+                push_repeated(&mut self.other, name.as_ref(), value, span);
+            }
+            Directive::ForwardReferencesAreResolved | Directive::StartOfFasta => {}
+        }
+    }
 
-    let mut value_records = Vec::new();
+    fn into_nuon(self, span: Span) -> Value {
+        let string = |s: Option<String>| s.map_or(Value::nothing(span), |s| span.with_string(s));
 
-    for record in reader.records() {
-        let r = match record {
-            Ok(rec) => rec,
-            Err(e) => {
-                return Err(LabeledError::new(format!("cause of failure: {}", e))
-                    .with_label("Record reading failed.", call.head))
+        Value::record(
+            record! {
+                "gff_version" => string(self.gff_version),
+                "sequence_region" => Value::record(self.sequence_region, span),
+                "species" => string(self.species),
+                "genome_build" => self.genome_build.unwrap_or(Value::nothing(span)),
+                "feature_ontology" => string(self.feature_ontology),
+                "attribute_ontology" => string(self.attribute_ontology),
+                "source_ontology" => string(self.source_ontology),
+                "other" => Value::record(self.other, span),
+            },
+            span,
+        )
+    }
+}
+
+/// Parse a GFF file into a record of its `directives`, its `features`, and the `sequences`
+/// in its `##FASTA` section (e.g. from Prokka or Bakta), which are the `id` and `sequence`
+/// of each, as with `from fasta`.
+pub fn from_gff_inner(
+    call: &EvaluatedCall,
+    input: Input,
+    gz: &Compression,
+) -> Result<Value, LabeledError> {
+    let mut reader = gff::Reader::new(decompress(input, gz));
+    let attributes = attributes_from(call)?;
+    let span = call.head;
+    let read_error = |e: std::io::Error| {
+        LabeledError::new(format!("cause of failure: {}", e))
+            .with_label("Record reading failed.", span)
+    };
+
+    let mut directives = Directives::default();
+    let mut features = Vec::new();
+    let mut has_fasta = false;
+
+    for line in reader.lines() {
+        match line.map_err(read_error)? {
+            gff::Line::Directive(gff::Directive::StartOfFasta) => {
+                has_fasta = true;
+                break;
             }
-        };
+            gff::Line::Directive(directive) => directives.add(directive, span),
+            gff::Line::Record(r) => features.push(record_to_nuon(r, &attributes, span)),
+            gff::Line::Comment(_) => {}
+        }
+    }
 
-        let mut vec_vals = Vec::new();
-        add_record(call, r, &mut vec_vals);
+    let mut sequences = Vec::new();
+    if has_fasta {
+        let mut fasta_reader = fasta::Reader::new(reader.into_inner());
+        for record in fasta_reader.records() {
+            let r = record.map_err(read_error)?;
+            sequences.push(Value::record(
+                record! {
+                    "id" => span.with_string(r.name()),
+                    "sequence" => span.with_string_from_utf8(r.sequence().as_ref())?,
+                },
+                span,
+            ));
+        }
+    }
 
-        let record_inner =
-            Record::from_iter(GFF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+    Ok(Value::record(
+        record! {
+            "directives" => directives.into_nuon(span),
+            "features" => Value::list(features, span),
+            "sequences" => Value::list(sequences, span),
+        },
+        span,
+    ))
+}
 
-        value_records.push(Value::record(record_inner, call.head))
+/// GTF attributes as a record, e.g. `gene_id "g1"; transcript_id "t1";`.
+///
+/// Keys which are repeated (e.g. GENCODE's `tag`) are lists.
+fn gtf_attributes_to_nuon(attributes: &gtf::record::Attributes, span: Span) -> Value {
+    let mut record = Record::new();
+
+    for entry in attributes.iter() {
+        push_repeated(
+            &mut record,
+            entry.key(),
+            span.with_string(entry.value()),
+            span,
+        );
     }
 
-    Ok(value_records)
+    Value::record(record, span)
+}
+
+/// A GTF record, with the same columns as GFF. The frame goes in the `phase` column.
+///
+/// GTF strands are `+` or `-`, so a missing strand is `null`.
+fn gtf_record_to_nuon(r: gtf::Record, attributes: &[String], span: Span) -> Value {
+    let start = usize::from(r.start());
+    let end = usize::from(r.end());
+
+    let values = vec![
+        span.with_string(r.reference_sequence_name()),
+        span.with_string(r.source()),
+        span.with_string(r.ty()),
+        Value::int(start as i64, span),
+        Value::int(end as i64, span),
+        r.score()
+            .map_or(Value::nothing(span), |score| float_to_nuon(score, span)),
+        r.strand()
+            .map_or(Value::nothing(span), |strand| span.with_string(strand)),
+        r.frame().map_or(Value::nothing(span), |frame| {
+            Value::int(i64::from(u8::from(frame)), span)
+        }),
+        gtf_attributes_to_nuon(r.attributes(), span),
+    ];
+
+    columns_to_nuon(values, attributes, span)
+}
+
+/// Parse a GTF file into a stream of nushell records.
+pub fn from_gtf_inner(
+    call: &EvaluatedCall,
+    input: Input,
+    gz: &Compression,
+) -> Result<ValueStream, LabeledError> {
+    let mut reader = gtf::Reader::new(decompress(input, gz));
+    let attributes = attributes_from(call)?;
+
+    let span = call.head;
+    let records = std::iter::from_fn(move || reader.records().next()).map(move |record| {
+        record
+            .map(|r| gtf_record_to_nuon(r, &attributes, span))
+            .map_err(|e| {
+                LabeledError::new(format!("cause of failure: {}", e))
+                    .with_label("Record reading failed.", span)
+            })
+    });
+
+    Ok(stream_records(records, span))
 }
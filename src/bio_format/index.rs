@@ -0,0 +1,245 @@
+/// Region queries over BGZF compressed files, using their index.
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use noodles::bam::bai;
+use noodles::bgzf::VirtualPosition;
+use noodles::core::{region::Interval, Region};
+use noodles::csi::{self, index::reference_sequence::bin::Chunk};
+use noodles::tabix;
+use nu_plugin::EvaluatedCall;
+use nu_protocol::LabeledError;
+
+use super::with_appended_extension;
+
+/// A chunk of a file to read, along with the region it was queried for, and the region
+/// before it on the same reference sequence, whose records have already been read.
+pub struct RegionChunk {
+    pub chunk: Chunk,
+    pub reference_sequence_id: usize,
+    pub region: Region,
+    pub previous: Option<Region>,
+}
+
+/// A reader of a BGZF compressed file which can be queried with an index.
+pub trait IndexedReader {
+    type Record;
+
+    fn seek(&mut self, pos: VirtualPosition) -> io::Result<VirtualPosition>;
+
+    fn virtual_position(&self) -> VirtualPosition;
+
+    /// Read the next record, or `None` at the end of the file.
+    fn read_next(&mut self) -> Option<io::Result<Self::Record>>;
+
+    /// Whether a record overlaps a region of a reference sequence.
+    fn intersects(
+        &self,
+        record: &Self::Record,
+        reference_sequence_id: usize,
+        region: &Region,
+    ) -> io::Result<bool>;
+}
+
+/// Records from the chunks of an indexed file which overlap the queried regions.
+///
+/// Unlike the noodles queries, this owns the reader, so the records can be streamed.
+pub struct Query<R> {
+    reader: R,
+    chunks: VecDeque<RegionChunk>,
+    current: Option<RegionChunk>,
+}
+
+impl<R: IndexedReader> Query<R> {
+    pub fn new(reader: R, chunks: VecDeque<RegionChunk>) -> Self {
+        Self {
+            reader,
+            chunks,
+            current: None,
+        }
+    }
+
+    /// Whether a record overlaps the region its chunk was queried for, and wasn't already
+    /// read for the region before it.
+    ///
+    /// The regions are sorted and don't overlap, so a record overlapping any earlier region
+    /// overlaps the one just before.
+    fn is_new(&self, record: &R::Record, chunk: &RegionChunk) -> io::Result<bool> {
+        let id = chunk.reference_sequence_id;
+        if !self.reader.intersects(record, id, &chunk.region)? {
+            return Ok(false);
+        }
+
+        match &chunk.previous {
+            Some(previous) => Ok(!self.reader.intersects(record, id, previous)?),
+            None => Ok(true),
+        }
+    }
+}
+
+impl<R: IndexedReader> Iterator for Query<R> {
+    type Item = io::Result<R::Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some(current) = &self.current else {
+                let next = self.chunks.pop_front()?;
+                if let Err(e) = self.reader.seek(next.chunk.start()) {
+                    return Some(Err(e));
+                }
+                self.current = Some(next);
+                continue;
+            };
+
+            if self.reader.virtual_position() >= current.chunk.end() {
+                self.current = None;
+                continue;
+            }
+
+            match self.reader.read_next() {
+                None => self.current = None,
+                Some(Ok(record)) => match self.is_new(&record, current) {
+                    Ok(true) => return Some(Ok(record)),
+                    Ok(false) => (),
+                    Err(e) => return Some(Err(e)),
+                },
+                Some(Err(e)) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Read the index of a file, either the one given, or the first one found next to
+/// the file with one of the extensions (e.g. `sample.vcf.gz.tbi`).
+pub fn read_index(
+    call: &EvaluatedCall,
+    path: &Path,
+    index: Option<PathBuf>,
+    extensions: &[&str],
+) -> Result<csi::Index, LabeledError> {
+    let index = index
+        .or_else(|| {
+            extensions
+                .iter()
+                .map(|extension| with_appended_extension(path, extension))
+                .find(|p| p.exists())
+        })
+        .ok_or_else(|| {
+            LabeledError::new(format!(
+                "no .{} index found for {}",
+                extensions.join(" or ."),
+                path.display()
+            ))
+            .with_label("Region queries need an index.", call.head)
+            .with_help("create one (e.g. with `samtools index` or `bcftools index`), or pass it with --index")
+        })?;
+
+    let result = match index.extension().and_then(|e| e.to_str()) {
+        Some("bai") => bai::read(&index),
+        Some("tbi") => tabix::read(&index),
+        _ => csi::read(&index),
+    };
+
+    result.map_err(|e| {
+        LabeledError::new(format!("cause of failure: {}", e)).with_label(
+            format!("Could not read the index {}.", index.display()),
+            call.head,
+        )
+    })
+}
+
+/// Find the chunks of the file overlapping each region.
+///
+/// Like `bcftools view -r`, the regions are sorted, and those which overlap or are next to
+/// each other are merged, so records come in the order they are in the file, and only once.
+///
+/// `resolve` maps the name of a region to the reference sequence ID used in the index.
+pub fn query_chunks(
+    call: &EvaluatedCall,
+    index: &csi::Index,
+    regions: Vec<Region>,
+    resolve: impl Fn(&str) -> Option<usize>,
+) -> Result<VecDeque<RegionChunk>, LabeledError> {
+    let regions = regions
+        .into_iter()
+        .map(|region| {
+            let reference_sequence_id = resolve(region.name()).ok_or_else(|| {
+                LabeledError::new(format!(
+                    "reference sequence {} is not in the index",
+                    region.name()
+                ))
+                .with_label("Unknown reference sequence.", call.head)
+            })?;
+            Ok((reference_sequence_id, region))
+        })
+        .collect::<Result<Vec<_>, LabeledError>>()?;
+
+    let mut chunks = VecDeque::new();
+    let mut previous: Option<(usize, Region)> = None;
+
+    for (reference_sequence_id, region) in merge_regions(regions) {
+        let region_chunks = index
+            .query(reference_sequence_id, region.interval())
+            .map_err(|e| {
+                LabeledError::new(format!("cause of failure: {}", e))
+                    .with_label(format!("Could not query region {region}."), call.head)
+            })?;
+
+        let previous_region = previous
+            .take()
+            .filter(|(id, _)| *id == reference_sequence_id)
+            .map(|(_, region)| region);
+
+        chunks.extend(region_chunks.into_iter().map(|chunk| RegionChunk {
+            chunk,
+            reference_sequence_id,
+            region: region.clone(),
+            previous: previous_region.clone(),
+        }));
+        previous = Some((reference_sequence_id, region));
+    }
+
+    Ok(chunks)
+}
+
+/// The first and last positions of a region, where a missing end is the end of the
+/// reference sequence.
+fn bounds(region: &Region) -> (usize, usize) {
+    let interval = region.interval();
+    (
+        interval.start().map_or(1, usize::from),
+        interval.end().map_or(usize::MAX, usize::from),
+    )
+}
+
+/// Sort regions by reference sequence and start, merging those which overlap or are next
+/// to each other.
+fn merge_regions(mut regions: Vec<(usize, Region)>) -> Vec<(usize, Region)> {
+    regions.sort_by_key(|(id, region)| (*id, bounds(region).0));
+
+    let mut merged: Vec<(usize, Region)> = Vec::new();
+    for (id, region) in regions {
+        if let Some((last_id, last)) = merged.last_mut() {
+            let (start, _) = bounds(&region);
+            let (_, last_end) = bounds(last);
+
+            if *last_id == id && start <= last_end.saturating_add(1) {
+                // an open end is the end of the reference sequence, so it wins
+                let start = last.interval().start();
+                let end = region.interval().end().zip(last.interval().end());
+                let interval: Interval = match (start, end.map(|(a, b)| a.max(b))) {
+                    (Some(start), Some(end)) => (start..=end).into(),
+                    (Some(start), None) => (start..).into(),
+                    (None, Some(end)) => (..=end).into(),
+                    (None, None) => (..).into(),
+                };
+                *last = Region::new(last.name(), interval);
+                continue;
+            }
+        }
+        merged.push((id, region));
+    }
+
+    merged
+}
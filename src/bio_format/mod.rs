@@ -1,3 +1,12 @@
+use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use flate2::bufread::MultiGzDecoder;
+use noodles::{bgzf, core::Region};
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{LabeledError, PipelineData, Record};
 pub use nu_protocol::{Span, Value};
 /// SAM + BAM parsing facility.
 pub mod bam;
@@ -11,8 +20,10 @@ pub mod cram;
 pub mod fasta;
 /// GFA parsing utility
 pub mod gfa;
-/// GFF(3) parsing facility
+/// GFF(3) and GTF parsing facility
 pub mod gff;
+/// Indexed region query facility.
+pub mod index;
 
 /// Compression enum
 #[derive(PartialEq)]
@@ -21,10 +32,160 @@ pub enum Compression {
     Gzipped,
 }
 
+/// A lazily parsed stream of records, ready to be handed to a `ListStream`.
+pub type ValueStream = Box<dyn Iterator<Item = Value> + Send>;
+
+/// Wrap an iterator of parsed records into a [`ValueStream`].
+///
+/// The first error is passed on as an error value, and ends the stream.
+pub fn stream_records<I>(records: I, span: Span) -> ValueStream
+where
+    I: Iterator<Item = Result<Value, LabeledError>> + Send + 'static,
+{
+    let mut failed = false;
+    Box::new(records.map_while(move |record| {
+        if failed {
+            return None;
+        }
+        Some(record.unwrap_or_else(|e| {
+            failed = true;
+            Value::error(e.into(), span)
+        }))
+    }))
+}
+
+/// The pipeline input, which the readers consume incrementally.
+pub type Input = Box<dyn BufRead + Send>;
+
+/// Turn the pipeline input into a reader.
+///
+/// Byte streams (e.g. from `open --raw`) are read as the parsers ask for more, so large
+/// files never have to be buffered in memory.
+pub fn input_to_reader(call: &EvaluatedCall, input: PipelineData) -> Result<Input, LabeledError> {
+    match input {
+        PipelineData::ByteStream(stream, ..) => Ok(match stream.reader() {
+            Some(reader) => Box::new(reader),
+            // a child process without stdout has nothing to read.
+            None => Box::new(io::empty()),
+        }),
+        PipelineData::Value(Value::Binary { val, .. }, ..) => Ok(Box::new(Cursor::new(val))),
+        PipelineData::Value(Value::String { val, .. }, ..) => {
+            Ok(Box::new(Cursor::new(val.into_bytes())))
+        }
+        other => Err(LabeledError::new(format!(
+            "requires binary or string input, got {}",
+            other.get_type()
+        ))
+        .with_label("Input should be binary or a string.", call.head)),
+    }
+}
+
+/// Decompress gzipped input, which can be BGZF or plain gzip.
+///
+/// BGZF is read with the same reader as the other bgzipped formats. Plain gzip has no block
+/// sizes for it to read, so falls back to decoding the gzip members one after another.
+pub fn decompress(mut input: Input, gz: &Compression) -> Input {
+    match gz {
+        Compression::Uncompressed => input,
+        Compression::Gzipped if is_bgzf(&mut input) => Box::new(bgzf::Reader::new(input)),
+        Compression::Gzipped => Box::new(BufReader::new(MultiGzDecoder::new(input))),
+    }
+}
+
+/// Whether the input starts with a BGZF block: a gzip header with the `BC` extra subfield
+/// holding the block size.
+fn is_bgzf(input: &mut Input) -> bool {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+    const FLG_FEXTRA: u8 = 0x04;
+
+    match input.fill_buf() {
+        Ok(header) if header.len() >= 14 => {
+            header[..2] == GZIP_MAGIC && header[3] & FLG_FEXTRA != 0 && &header[12..14] == b"BC"
+        }
+        _ => false,
+    }
+}
+
+/// Parse the regions passed to `--region`, given either as a single region or a list of them.
+pub fn regions_from(call: &EvaluatedCall) -> Result<Option<Vec<Region>>, LabeledError> {
+    let Some(value) = call.get_flag_value("region") else {
+        return Ok(None);
+    };
+
+    let regions = match &value {
+        Value::List { vals, .. } => vals.iter().map(Value::as_str).collect::<Result<_, _>>()?,
+        other => vec![other.as_str()?],
+    };
+
+    regions
+        .into_iter()
+        .map(|region| parse_region(region, value.span()))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+/// Parse a region such as `chr1:100-200`, or `chr1` for the whole reference sequence.
+pub fn parse_region(region: &str, span: Span) -> Result<Region, LabeledError> {
+    region.parse().map_err(|e| {
+        LabeledError::new(format!("invalid region {region}: {e}"))
+            .with_label("Could not parse region.", span)
+    })
+}
+
+/// Widen a float from a record, keeping the value as it was written (e.g. `0.1` rather
+/// than `0.10000000149011612`).
+pub fn float_to_nuon(f: f32, span: Span) -> Value {
+    Value::float(f.to_string().parse().unwrap_or(f64::from(f)), span)
+}
+
+/// A value in a row, `None` if the column is missing or null.
+pub fn optional<'a>(row: &'a Record, column: &str) -> Option<&'a Value> {
+    row.get(column).filter(|value| !value.is_nothing())
+}
+
+/// A value in a row which has to be there.
+pub fn required<'a>(row: &'a Record, column: &str, span: Span) -> Result<&'a Value, LabeledError> {
+    row.get(column).ok_or_else(|| {
+        LabeledError::new(format!("missing column {column}"))
+            .with_label(format!("The {column} column is required."), span)
+    })
+}
+
+/// Parse a string from a nushell value into a noodles type.
+pub fn parse_value<T>(value: &Value, column: &str) -> Result<T, LabeledError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = value.as_str()?;
+    s.parse().map_err(|e| {
+        LabeledError::new(format!("invalid {column} {s}: {e}"))
+            .with_label(format!("Could not parse {column}."), value.span())
+    })
+}
+
+/// Convert an int from a nushell value into a narrower integer type.
+pub fn int_value<T: TryFrom<i64>>(value: &Value, column: &str) -> Result<T, LabeledError> {
+    let i = value.as_int()?;
+    T::try_from(i).map_err(|_| {
+        LabeledError::new(format!("{column} {i} is out of range"))
+            .with_label(format!("Could not convert {column}."), value.span())
+    })
+}
+
+/// Append an extension to a path, e.g. to find `sample.bam.bai` from `sample.bam`.
+pub fn with_appended_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut appended = path.as_os_str().to_owned();
+    appended.push(".");
+    appended.push(extension);
+    PathBuf::from(appended)
+}
+
 pub trait SpanExt {
     fn with_string<S: ToString>(&self, s: S) -> Value;
     fn with_string_or<S: ToString>(&self, s: Option<S>, default: &str) -> Value;
-    fn with_string_from_utf8(&self, s: &[u8]) -> Value;
+    fn with_string_or_null<S: ToString>(&self, s: Option<S>) -> Value;
+    fn with_string_from_utf8(&self, s: &[u8]) -> Result<Value, LabeledError>;
 }
 
 impl SpanExt for Span {
@@ -35,8 +196,15 @@ impl SpanExt for Span {
     fn with_string_or<S: ToString>(&self, s: Option<S>, default: &str) -> Value {
         Value::string(s.map(|s| s.to_string()).unwrap_or(default.into()), *self)
     }
-    fn with_string_from_utf8(&self, s: &[u8]) -> Value {
-        // TODO: remove this unwrap
-        self.with_string(std::str::from_utf8(s).unwrap())
+
+    fn with_string_or_null<S: ToString>(&self, s: Option<S>) -> Value {
+        s.map_or(Value::nothing(*self), |s| self.with_string(s))
+    }
+    fn with_string_from_utf8(&self, s: &[u8]) -> Result<Value, LabeledError> {
+        let s = std::str::from_utf8(s).map_err(|e| {
+            LabeledError::new(format!("cause of failure: {e}"))
+                .with_label("Could not read the text as UTF-8.", *self)
+        })?;
+        Ok(self.with_string(s))
     }
 }
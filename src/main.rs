@@ -2,5 +2,5 @@ use nu_plugin::{serve_plugin, MsgPackSerializer};
 use nu_plugin_bio::Bio;
 
 fn main() {
-    serve_plugin(&mut Bio {}, MsgPackSerializer {})
+    serve_plugin(&Bio {}, MsgPackSerializer {})
 }
@@ -0,0 +1,59 @@
+use crate::bio::faidx;
+use nu_plugin::PluginCommand;
+use nu_protocol::{Signature, SyntaxShape, Type};
+
+pub struct Command;
+
+impl PluginCommand for Command {
+    type Plugin = crate::Bio;
+
+    fn name(&self) -> &str {
+        "bio faidx"
+    }
+
+    fn description(&self) -> &str {
+        "Extract regions from a FASTA file with its .fai index (and .gzi, if bgzipped).\nWith no regions, returns the index."
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build(<Self as PluginCommand>::name(self))
+            .input_output_types(vec![(Type::Nothing, Type::table())])
+            .category(nu_protocol::Category::Formats)
+            .required(
+                "file",
+                SyntaxShape::Filepath,
+                "the FASTA file, plain or bgzipped",
+            )
+            .rest(
+                "regions",
+                SyntaxShape::String,
+                "regions to extract (e.g. chr1:100-200, or chr1 for the whole sequence)",
+            )
+            .switch(
+                "reverse-complement",
+                "reverse complement the extracted sequences",
+                Some('c'),
+            )
+            .switch(
+                "build",
+                "create the .fai index if it is missing (plain FASTA only)",
+                Some('b'),
+            )
+            .named(
+                "index",
+                SyntaxShape::Filepath,
+                "path to the .fai index, if not next to the file",
+                Some('i'),
+            )
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &nu_plugin::EngineInterface,
+        call: &nu_plugin::EvaluatedCall,
+        _input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        faidx(call, engine)
+    }
+}
@@ -1,48 +1,46 @@
-use nu_plugin::SimplePluginCommand;
-use nu_protocol::{Signature, Type};
+use nu_plugin::PluginCommand;
+use nu_protocol::Signature;
 
 use crate::bio::{from_bam, from_sam};
 
+/// The parser behind a command.
+type Runner = Box<
+    dyn Sync
+        + Fn(
+            &nu_plugin::EvaluatedCall,
+            nu_protocol::PipelineData,
+            &nu_plugin::EngineInterface,
+        ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError>,
+>;
+
 pub struct Command {
     name: String,
     description: String,
-    run: Box<
-        dyn Sync
-            + Fn(
-                &nu_plugin::EvaluatedCall,
-                &nu_protocol::Value,
-            ) -> Result<nu_protocol::Value, nu_protocol::LabeledError>,
-    >,
+    run: Runner,
+    /// The index extensions, if the format can be queried by region.
+    index: Option<&'static str>,
 }
 
 impl Command {
-    fn new(
-        file: &str,
-        run: Box<
-            dyn Sync
-                + Fn(
-                    &nu_plugin::EvaluatedCall,
-                    &nu_protocol::Value,
-                ) -> Result<nu_protocol::Value, nu_protocol::LabeledError>,
-        >,
-    ) -> Self {
+    fn new(file: &str, run: Runner, index: Option<&'static str>) -> Self {
         let upper = file.to_uppercase();
         Self{
 			name: format!("from {}", file.to_lowercase()),
-			description: format!("Parse a {upper} file.\nReturns a record containing the header and the body of the {upper} file."),
-			run
+			description: format!("Parse a {upper} file.\nStreams the alignments, or returns a record containing the header and the body of the {upper} file with --header."),
+			run,
+			index,
 		}
     }
 }
 
 pub fn command_sam() -> Command {
-    Command::new("sam", Box::new(|call, input| from_sam(call, input)))
+    Command::new("sam", Box::new(from_sam), None)
 }
 pub fn command_bam() -> Command {
-    Command::new("bam", Box::new(|call, input| from_bam(call, input)))
+    Command::new("bam", Box::new(from_bam), Some(".bai or .csi"))
 }
 
-impl SimplePluginCommand for Command {
+impl PluginCommand for Command {
     type Plugin = crate::Bio;
 
     fn name(&self) -> &str {
@@ -54,18 +52,24 @@ impl SimplePluginCommand for Command {
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        Signature::build(<Self as SimplePluginCommand>::name(self))
-            .input_output_types(vec![(Type::String, Type::table())])
-            .category(nu_protocol::Category::Formats)
+        let signature = super::with_tags_flag(super::with_header_flag(
+            Signature::build(<Self as PluginCommand>::name(self))
+                .category(nu_protocol::Category::Formats),
+        ));
+
+        match self.index {
+            Some(index) => super::with_region_flags(signature, index),
+            None => signature,
+        }
     }
 
     fn run(
         &self,
         _plugin: &Self::Plugin,
-        _engine: &nu_plugin::EngineInterface,
+        engine: &nu_plugin::EngineInterface,
         call: &nu_plugin::EvaluatedCall,
-        input: &nu_protocol::Value,
-    ) -> Result<nu_protocol::Value, nu_protocol::LabeledError> {
-        (self.run)(call, input)
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        (self.run)(call, input, engine)
     }
 }
@@ -2,82 +2,77 @@ use crate::{
     bio::{from_bcf, from_vcf},
     bio_format::Compression,
 };
-use nu_plugin::SimplePluginCommand;
-use nu_protocol::{Signature, Type};
+use nu_plugin::PluginCommand;
+use nu_protocol::{Signature, SyntaxShape};
 
 pub fn bcf() -> Command {
     Command::new(
         "bcf",
         Compression::Uncompressed,
-        Box::new(|call, input, c| from_bcf(call, input, c)),
+        Box::new(from_bcf),
+        Some(".csi"),
     )
 }
 
 pub fn bcf_gz() -> Command {
-    Command::new(
-        "bcf",
-        Compression::Gzipped,
-        Box::new(|call, input, c| from_bcf(call, input, c)),
-    )
+    Command::new("bcf", Compression::Gzipped, Box::new(from_bcf), None)
 }
 
 pub fn vcf() -> Command {
-    Command::new(
-        "vcf",
-        Compression::Uncompressed,
-        Box::new(|call, input, c| from_vcf(call, input, c)),
-    )
+    Command::new("vcf", Compression::Uncompressed, Box::new(from_vcf), None)
 }
 
 pub fn vcf_gz() -> Command {
     Command::new(
         "vcf",
         Compression::Gzipped,
-        Box::new(|call, input, c| from_vcf(call, input, c)),
+        Box::new(from_vcf),
+        Some(".tbi or .csi"),
     )
 }
 
+/// The parser behind a command.
+type Runner = Box<
+    dyn Sync
+        + Fn(
+            &nu_plugin::EvaluatedCall,
+            nu_protocol::PipelineData,
+            &Compression,
+            &nu_plugin::EngineInterface,
+        ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError>,
+>;
+
 pub struct Command {
     name: String,
     description: String,
     compression: Compression,
-    runner: Box<
-        dyn Sync
-            + Fn(
-                &nu_plugin::EvaluatedCall,
-                &nu_protocol::Value,
-                &Compression,
-            ) -> Result<nu_protocol::Value, nu_protocol::LabeledError>,
-    >,
+    runner: Runner,
+    /// The index extensions, if the format can be queried by region.
+    index: Option<&'static str>,
 }
 
 impl Command {
     fn new(
         filename: &str,
         compression: Compression,
-        runner: Box<
-            dyn Sync
-                + Fn(
-                    &nu_plugin::EvaluatedCall,
-                    &nu_protocol::Value,
-                    &Compression,
-                ) -> Result<nu_protocol::Value, nu_protocol::LabeledError>,
-        >,
+        runner: Runner,
+        index: Option<&'static str>,
     ) -> Self {
         let uppercase = filename.to_uppercase();
         Self {
 			name: format!("from {}", super::file_name_from(&filename.to_string(), &compression)),
 			description: match &compression {
-				Compression::Uncompressed => format!("Parse a {uppercase} file.\nReturns a record containing the header and the body of the {uppercase} file."),
-				Compression::Gzipped => format!("Parse a gzipped {uppercase} file.\nReturns a record containing the header and the body of the {uppercase} file."),
+				Compression::Uncompressed => format!("Parse a {uppercase} file.\nStreams the variants, or returns a record containing the header and the body of the {uppercase} file with --header."),
+				Compression::Gzipped => format!("Parse a gzipped {uppercase} file.\nStreams the variants, or returns a record containing the header and the body of the {uppercase} file with --header."),
 			},
 			runner,
 			compression,
+			index,
 		}
     }
 }
 
-impl SimplePluginCommand for Command {
+impl PluginCommand for Command {
     type Plugin = crate::Bio;
 
     fn name(&self) -> &str {
@@ -89,19 +84,44 @@ impl SimplePluginCommand for Command {
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        Signature::build(<Self as SimplePluginCommand>::name(self))
-            .input_output_types(vec![(Type::String, Type::table())])
-            .category(nu_protocol::Category::Formats)
+        let signature = super::with_header_flag(
+            Signature::build(<Self as PluginCommand>::name(self))
+                .category(nu_protocol::Category::Formats),
+        )
+        .named(
+                "samples",
+                SyntaxShape::OneOf(vec![
+                    SyntaxShape::List(Box::new(SyntaxShape::String)),
+                    SyntaxShape::String,
+                ]),
+                "keep only the genotypes of these samples (e.g. NA12878,NA12891)",
+                Some('s'),
+            )
+            .switch(
+                "long",
+                "one row per variant per sample, with its genotype and FORMAT fields as columns",
+                Some('l'),
+            )
+            .switch(
+                "annotations",
+                "split SnpEff ANN, VEP CSQ and bcftools BCSQ fields into records, named from the header",
+                Some('a'),
+            );
+
+        match self.index {
+            Some(index) => super::with_region_flags(signature, index),
+            None => signature,
+        }
     }
 
     fn run(
         &self,
         _plugin: &Self::Plugin,
-        _engine: &nu_plugin::EngineInterface,
+        engine: &nu_plugin::EngineInterface,
         call: &nu_plugin::EvaluatedCall,
-        input: &nu_protocol::Value,
-    ) -> Result<nu_protocol::Value, nu_protocol::LabeledError> {
-        (self.runner)(call, input, &self.compression)
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        (self.runner)(call, input, &self.compression, engine)
     }
 }
 
@@ -1,33 +1,59 @@
-use nu_plugin::SimplePluginCommand;
+use nu_plugin::PluginCommand;
 use nu_protocol::{Signature, Type};
 
-use crate::bio::from_bed;
-pub struct Command;
+use crate::{bio::from_bed, bio_format::Compression};
 
-impl SimplePluginCommand for Command {
+pub struct Command {
+    name: String,
+    description: String,
+    compression: Compression,
+}
+
+pub fn bed() -> Command {
+    new(Compression::Uncompressed)
+}
+pub fn bed_gz() -> Command {
+    new(Compression::Gzipped)
+}
+
+fn new(compression: Compression) -> Command {
+    Command {
+        name: format!("from {}", super::file_name_from(&"bed", &compression)),
+        description: match compression {
+            Compression::Uncompressed => "Parse a BED file.".into(),
+            Compression::Gzipped => "Parse a gzipped or bgzipped BED file.".into(),
+        },
+        compression,
+    }
+}
+
+impl PluginCommand for Command {
     type Plugin = crate::Bio;
 
     fn name(&self) -> &str {
-        "from bed"
+        &self.name
     }
 
     fn description(&self) -> &str {
-        "Parse a BED file."
+        &self.description
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        Signature::build(<Self as SimplePluginCommand>::name(self))
-            .input_output_types(vec![(Type::String, Type::table())])
+        Signature::build(<Self as PluginCommand>::name(self))
+            .input_output_types(vec![
+                (Type::String, Type::table()),
+                (Type::Binary, Type::table()),
+            ])
             .category(nu_protocol::Category::Formats)
     }
 
     fn run(
         &self,
         _plugin: &Self::Plugin,
-        _engine: &nu_plugin::EngineInterface,
+        engine: &nu_plugin::EngineInterface,
         call: &nu_plugin::EvaluatedCall,
-        input: &nu_protocol::Value,
-    ) -> Result<nu_protocol::Value, nu_protocol::LabeledError> {
-        from_bed(call, input)
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        from_bed(call, input, &self.compression, engine)
     }
 }
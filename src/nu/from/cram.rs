@@ -1,10 +1,10 @@
 use crate::bio::from_cram;
-use nu_plugin::SimplePluginCommand;
-use nu_protocol::{Signature, Type};
+use nu_plugin::PluginCommand;
+use nu_protocol::{Signature, SyntaxShape};
 
 pub struct Command;
 
-impl SimplePluginCommand for Command {
+impl PluginCommand for Command {
     type Plugin = crate::Bio;
 
     fn name(&self) -> &str {
@@ -12,22 +12,28 @@ impl SimplePluginCommand for Command {
     }
 
     fn description(&self) -> &str {
-        "Parse a CRAM file into SAM output.\nReturns a record containing the header and the body of the CRAM file."
+        "Parse a CRAM file into SAM output.\nStreams the alignments, or returns a record containing the header and the body of the CRAM file with --header."
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        Signature::build(<Self as SimplePluginCommand>::name(self))
-            .input_output_types(vec![(Type::String, Type::table())])
-            .category(nu_protocol::Category::Formats)
+        super::with_tags_flag(super::with_header_flag(Signature::build(<Self as PluginCommand>::name(self))
+            .category(nu_protocol::Category::Formats))
+            .named(
+                "reference",
+                SyntaxShape::Filepath,
+                "the reference FASTA the CRAM was made with, to restore the sequences (uses its .fai if present)",
+                Some('R'),
+            ),
+        )
     }
 
     fn run(
         &self,
         _plugin: &Self::Plugin,
-        _engine: &nu_plugin::EngineInterface,
+        engine: &nu_plugin::EngineInterface,
         call: &nu_plugin::EvaluatedCall,
-        input: &nu_protocol::Value,
-    ) -> Result<nu_protocol::Value, nu_protocol::LabeledError> {
-        from_cram(call, input)
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        from_cram(call, input, engine)
     }
 }
@@ -1,7 +1,7 @@
 use super::{file_extension_from, file_name_from};
 use crate::{bio::from_fasta, bio_format::Compression};
-use nu_plugin::SimplePluginCommand;
-use nu_protocol::{Signature, Type, Value};
+use nu_plugin::PluginCommand;
+use nu_protocol::{Signature, Type};
 
 enum File {
     Fasta,
@@ -63,7 +63,7 @@ pub fn command_fa_gz() -> Command {
     Command::new(File::Fa, Compression::Gzipped)
 }
 
-impl SimplePluginCommand for Command {
+impl PluginCommand for Command {
     type Plugin = crate::nu::Bio;
 
     fn name(&self) -> &str {
@@ -75,8 +75,11 @@ impl SimplePluginCommand for Command {
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        Signature::build(<Self as SimplePluginCommand>::name(self))
-            .input_output_types(vec![(Type::String, Type::table())])
+        Signature::build(<Self as PluginCommand>::name(self))
+            .input_output_types(vec![
+                (Type::String, Type::table()),
+                (Type::Binary, Type::table()),
+            ])
             .category(nu_protocol::Category::Formats)
             .switch(
                 "description",
@@ -88,10 +91,10 @@ impl SimplePluginCommand for Command {
     fn run(
         &self,
         _plugin: &Self::Plugin,
-        _engine: &nu_plugin::EngineInterface,
+        engine: &nu_plugin::EngineInterface,
         call: &nu_plugin::EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, nu_protocol::LabeledError> {
-        from_fasta(call, input, &self.compression)
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        from_fasta(call, input, &self.compression, engine)
     }
 }
@@ -1,5 +1,5 @@
-use nu_plugin::SimplePluginCommand;
-use nu_protocol::{Signature, Type, Value};
+use nu_plugin::PluginCommand;
+use nu_protocol::{Signature, Type};
 
 use crate::{bio::from_fastq, bio_format::Compression};
 
@@ -63,7 +63,7 @@ impl Command {
     }
 }
 
-impl SimplePluginCommand for Command {
+impl PluginCommand for Command {
     type Plugin = crate::nu::Bio;
 
     fn name(&self) -> &str {
@@ -77,8 +77,11 @@ impl SimplePluginCommand for Command {
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        Signature::build(<Self as SimplePluginCommand>::name(self))
-            .input_output_types(vec![(Type::String, Type::table())])
+        Signature::build(<Self as PluginCommand>::name(self))
+            .input_output_types(vec![
+                (Type::String, Type::table()),
+                (Type::Binary, Type::table()),
+            ])
             .category(nu_protocol::Category::Formats)
             .switch(
                 "description",
@@ -95,12 +98,12 @@ impl SimplePluginCommand for Command {
     fn run(
         &self,
         _plugin: &Self::Plugin,
-        _engine: &nu_plugin::EngineInterface,
+        engine: &nu_plugin::EngineInterface,
         call: &nu_plugin::EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, nu_protocol::LabeledError> {
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
         {
-            from_fastq(call, input, &self.compression)
+            from_fastq(call, input, &self.compression, engine)
         }
     }
 }
@@ -1,5 +1,5 @@
 use crate::{bio::from_gfa, bio_format::Compression};
-use nu_plugin::SimplePluginCommand;
+use nu_plugin::PluginCommand;
 use nu_protocol::{Signature, Type};
 
 pub struct Command {
@@ -19,14 +19,14 @@ fn new(compression: Compression) -> Command {
     Command{
 		name: format!("from {}", super::file_name_from(&"gfa".to_string(), &compression)),
 		description: match compression{
-			Compression::Uncompressed => "Parse a GFA file.\nReturns a record containing the header, segments, links, containments, and paths.".into(),
-			Compression::Gzipped => "Parse a gzipped GFA file.\nReturns a record containing the header, segments, links, containments, and paths.".into(),
+			Compression::Uncompressed => "Parse a GFA file.\nStreams the header, segments, links, containments, and paths, with what they are as the type column.".into(),
+			Compression::Gzipped => "Parse a gzipped GFA file.\nStreams the header, segments, links, containments, and paths, with what they are as the type column.".into(),
 		},
 		compression,
 	}
 }
 
-impl SimplePluginCommand for Command {
+impl PluginCommand for Command {
     type Plugin = crate::Bio;
 
     fn name(&self) -> &str {
@@ -38,18 +38,21 @@ impl SimplePluginCommand for Command {
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        Signature::build(<Self as SimplePluginCommand>::name(self))
-            .input_output_types(vec![(Type::String, Type::table())])
+        Signature::build(<Self as PluginCommand>::name(self))
+            .input_output_types(vec![
+                (Type::String, Type::table()),
+                (Type::Binary, Type::table()),
+            ])
             .category(nu_protocol::Category::Formats)
     }
 
     fn run(
         &self,
         _plugin: &Self::Plugin,
-        _engine: &nu_plugin::EngineInterface,
+        engine: &nu_plugin::EngineInterface,
         call: &nu_plugin::EvaluatedCall,
-        input: &nu_protocol::Value,
-    ) -> Result<nu_protocol::Value, nu_protocol::LabeledError> {
-        from_gfa(call, input, &self.compression)
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        from_gfa(call, input, &self.compression, engine)
     }
 }
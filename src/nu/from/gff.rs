@@ -1,33 +1,88 @@
-use crate::bio::from_gff;
-use nu_plugin::SimplePluginCommand;
+use super::file_name_from;
+use crate::{bio::from_gff, bio_format::Compression};
+use nu_plugin::PluginCommand;
 use nu_protocol::{Signature, Type};
 
-pub struct Command;
+enum File {
+    Gff,
+    Gff3,
+}
+
+impl std::fmt::Display for File {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                File::Gff => "gff",
+                File::Gff3 => "gff3",
+            }
+        )
+    }
+}
+
+pub struct Command {
+    name: String,
+    description: String,
+    compression: Compression,
+}
+
+impl Command {
+    fn new(f: File, c: Compression) -> Self {
+        Self {
+            name: format!("from {}", file_name_from(&f, &c)),
+            description: match c {
+                Compression::Uncompressed => "Parse a GFF file.\nStreams the features, or returns a record containing the directives, the features, and the sequences of any ##FASTA section with --header.".into(),
+                Compression::Gzipped => "Parse a gzipped or bgzipped GFF file.\nStreams the features, or returns a record containing the directives, the features, and the sequences of any ##FASTA section with --header.".into(),
+            },
+            compression: c,
+        }
+    }
+}
+
+pub fn command_gff() -> Command {
+    Command::new(File::Gff, Compression::Uncompressed)
+}
+
+pub fn command_gff_gz() -> Command {
+    Command::new(File::Gff, Compression::Gzipped)
+}
+
+pub fn command_gff3_gz() -> Command {
+    Command::new(File::Gff3, Compression::Gzipped)
+}
 
-impl SimplePluginCommand for Command {
+impl PluginCommand for Command {
     type Plugin = crate::Bio;
 
     fn name(&self) -> &str {
-        "from gff"
+        &self.name
     }
 
     fn description(&self) -> &str {
-        "Parse a GFF file.\nReturns a table."
+        &self.description
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        Signature::build(<Self as SimplePluginCommand>::name(self))
-            .input_output_types(vec![(Type::String, Type::table())])
-            .category(nu_protocol::Category::Formats)
+        super::with_attributes_flag(
+            Signature::build(<Self as PluginCommand>::name(self))
+                .input_output_types(vec![(Type::String, Type::Any), (Type::Binary, Type::Any)])
+                .category(nu_protocol::Category::Formats)
+                .switch(
+                    "header",
+                    "return a record of the directives, the features, which are read into memory, and the sequences of any ##FASTA section, rather than streaming the features",
+                    Some('H'),
+                ),
+        )
     }
 
     fn run(
         &self,
         _plugin: &Self::Plugin,
-        _engine: &nu_plugin::EngineInterface,
+        engine: &nu_plugin::EngineInterface,
         call: &nu_plugin::EvaluatedCall,
-        input: &nu_protocol::Value,
-    ) -> Result<nu_protocol::Value, nu_protocol::LabeledError> {
-        from_gff(call, input)
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        from_gff(call, input, &self.compression, engine)
     }
 }
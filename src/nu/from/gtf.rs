@@ -0,0 +1,64 @@
+use crate::{bio::from_gtf, bio_format::Compression};
+use nu_plugin::PluginCommand;
+use nu_protocol::{Signature, Type};
+
+pub struct Command {
+    name: String,
+    description: String,
+    compression: Compression,
+}
+
+pub fn gtf() -> Command {
+    new(Compression::Uncompressed)
+}
+pub fn gtf_gz() -> Command {
+    new(Compression::Gzipped)
+}
+
+fn new(compression: Compression) -> Command {
+    Command {
+        name: format!("from {}", super::file_name_from(&"gtf", &compression)),
+        description: match compression {
+            Compression::Uncompressed => {
+                "Parse a GTF file.\nReturns a table, with the same columns as GFF.".into()
+            }
+            Compression::Gzipped => {
+                "Parse a gzipped or bgzipped GTF file.\nReturns a table, with the same columns as GFF.".into()
+            }
+        },
+        compression,
+    }
+}
+
+impl PluginCommand for Command {
+    type Plugin = crate::Bio;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        super::with_attributes_flag(
+            Signature::build(<Self as PluginCommand>::name(self))
+                .input_output_types(vec![
+                    (Type::String, Type::table()),
+                    (Type::Binary, Type::table()),
+                ])
+                .category(nu_protocol::Category::Formats),
+        )
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &nu_plugin::EngineInterface,
+        call: &nu_plugin::EvaluatedCall,
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        from_gtf(call, input, &self.compression, engine)
+    }
+}
@@ -1,4 +1,5 @@
 use crate::bio_format::Compression;
+use nu_protocol::{Signature, SyntaxShape, Type};
 
 pub mod bam;
 pub mod bcf;
@@ -8,14 +9,73 @@ pub mod fasta;
 pub mod fastq;
 pub mod gfa;
 pub mod gff;
+pub mod gtf;
 
 fn file_extension_from(displayable: &dyn std::fmt::Display, c: &Compression) -> String {
     format!(".{}", file_name_from(displayable, c))
 }
 
-fn file_name_from(displayable: &dyn std::fmt::Display, c: &Compression) -> String {
+pub(crate) fn file_name_from(displayable: &dyn std::fmt::Display, c: &Compression) -> String {
     match c {
         Compression::Uncompressed => format!("{displayable}",),
         Compression::Gzipped => format!("{displayable}.gz",),
     }
 }
+
+/// Add the flags for querying regions of an indexed file to a signature.
+fn with_region_flags(signature: Signature, index: &str) -> Signature {
+    signature
+        .named(
+            "region",
+            SyntaxShape::OneOf(vec![
+                SyntaxShape::List(Box::new(SyntaxShape::String)),
+                SyntaxShape::String,
+            ]),
+            "only return records overlapping these regions (e.g. chr1:100-200), read via the index",
+            Some('r'),
+        )
+        .named(
+            "index",
+            SyntaxShape::Filepath,
+            format!("path to the index, if not next to the file as {index}"),
+            Some('i'),
+        )
+}
+
+/// Add the switch for returning a record of the header and the body, rather than streaming
+/// the body, to a signature. The output is a table, or a record with the switch, so either.
+fn with_header_flag(signature: Signature) -> Signature {
+    signature
+        .input_output_types(vec![(Type::String, Type::Any), (Type::Binary, Type::Any)])
+        .switch(
+            "header",
+            "return a record of the header and the body, which is read into memory, rather than streaming the body",
+            Some('H'),
+        )
+}
+
+/// Add the flag for pulling auxiliary data tags out into columns to an alignment signature.
+fn with_tags_flag(signature: Signature) -> Signature {
+    signature.named(
+        "tags",
+        SyntaxShape::OneOf(vec![
+            SyntaxShape::List(Box::new(SyntaxShape::String)),
+            SyntaxShape::String,
+        ]),
+        "add these auxiliary data tags as columns of their own (e.g. NM,AS)",
+        Some('t'),
+    )
+}
+
+/// Add the flag for pulling attributes out into columns to a GFF or GTF signature.
+fn with_attributes_flag(signature: Signature) -> Signature {
+    signature.named(
+        "attributes",
+        SyntaxShape::OneOf(vec![
+            SyntaxShape::List(Box::new(SyntaxShape::String)),
+            SyntaxShape::String,
+        ]),
+        "add these attributes as columns of their own (e.g. ID,Name,Parent)",
+        Some('a'),
+    )
+}
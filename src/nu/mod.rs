@@ -1,5 +1,6 @@
 use nu_plugin::Plugin;
 
+pub mod faidx;
 pub mod from;
 pub mod to;
 
@@ -12,13 +13,15 @@ impl Plugin for Bio {
 
     fn commands(&self) -> Vec<Box<dyn nu_plugin::PluginCommand<Plugin = Self>>> {
         vec![
+            Box::new(faidx::Command),
             Box::new(from::bam::command_bam()),
             Box::new(from::bam::command_sam()),
             Box::new(from::bcf::bcf()),
             Box::new(from::bcf::bcf_gz()),
             Box::new(from::bcf::vcf()),
             Box::new(from::bcf::vcf_gz()),
-            Box::new(from::bed::Command),
+            Box::new(from::bed::bed()),
+            Box::new(from::bed::bed_gz()),
             Box::new(from::cram::Command),
             Box::new(from::fasta::command_fasta()),
             Box::new(from::fasta::command_fa()),
@@ -30,9 +33,18 @@ impl Plugin for Bio {
             Box::new(from::fastq::Command::fq_gz()),
             Box::new(from::gfa::gfa()),
             Box::new(from::gfa::gfa_gz()),
-            Box::new(from::gff::Command),
+            Box::new(from::gff::command_gff()),
+            Box::new(from::gff::command_gff_gz()),
+            Box::new(from::gff::command_gff3_gz()),
+            Box::new(from::gtf::gtf()),
+            Box::new(from::gtf::gtf_gz()),
+            Box::new(to::bam::Command),
+            Box::new(to::bcf::bcf()),
             Box::new(to::fasta::Command),
             Box::new(to::fastq::Command),
+            Box::new(to::sam::Command),
+            Box::new(to::bcf::vcf()),
+            Box::new(to::bcf::vcf_gz()),
         ]
     }
 }
@@ -0,0 +1,33 @@
+use crate::bio::to_bam;
+use nu_plugin::PluginCommand;
+use nu_protocol::{Signature, Type};
+
+pub struct Command;
+
+impl PluginCommand for Command {
+    type Plugin = crate::Bio;
+
+    fn name(&self) -> &str {
+        "to bam"
+    }
+
+    fn description(&self) -> &str {
+        "Write alignments out as BGZF compressed BAM.\nTakes the record of the header and the body returned by `from sam --header` or `from bam --header`."
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build(<Self as PluginCommand>::name(self))
+            .input_output_types(vec![(Type::Any, Type::Binary)])
+            .category(nu_protocol::Category::Formats)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        _engine: &nu_plugin::EngineInterface,
+        call: &nu_plugin::EvaluatedCall,
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        to_bam(call, input)
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{
+    bio::{to_bcf, to_vcf},
+    bio_format::Compression,
+};
+use nu_plugin::PluginCommand;
+use nu_protocol::{Signature, Type};
+
+pub fn bcf() -> Command {
+    Command::new(
+        "bcf",
+        Compression::Uncompressed,
+        // BCF is always BGZF compressed
+        Box::new(|call, input, _| to_bcf(call, input)),
+        Type::Binary,
+    )
+}
+
+pub fn vcf() -> Command {
+    Command::new(
+        "vcf",
+        Compression::Uncompressed,
+        Box::new(to_vcf),
+        Type::String,
+    )
+}
+
+pub fn vcf_gz() -> Command {
+    Command::new("vcf", Compression::Gzipped, Box::new(to_vcf), Type::Binary)
+}
+
+/// The writer behind a command.
+type Runner = Box<
+    dyn Sync
+        + Fn(
+            &nu_plugin::EvaluatedCall,
+            nu_protocol::PipelineData,
+            &Compression,
+        ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError>,
+>;
+
+pub struct Command {
+    name: String,
+    description: String,
+    compression: Compression,
+    runner: Runner,
+    output: Type,
+}
+
+impl Command {
+    fn new(filename: &str, compression: Compression, runner: Runner, output: Type) -> Self {
+        let uppercase = filename.to_uppercase();
+        Self {
+			name: format!("to {}", crate::nu::from::file_name_from(&filename.to_string(), &compression)),
+			description: match &compression {
+				Compression::Uncompressed => format!("Write variants out as {uppercase}.\nTakes the record of the header and the body returned by `from vcf --header` or `from bcf --header`."),
+				Compression::Gzipped => format!("Write variants out as BGZF compressed {uppercase}.\nTakes the record of the header and the body returned by `from vcf --header` or `from bcf --header`."),
+			},
+			runner,
+			compression,
+			output,
+		}
+    }
+}
+
+impl PluginCommand for Command {
+    type Plugin = crate::Bio;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn signature(&self) -> nu_protocol::Signature {
+        Signature::build(<Self as PluginCommand>::name(self))
+            .input_output_types(vec![(Type::Any, self.output.clone())])
+            .category(nu_protocol::Category::Formats)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        _engine: &nu_plugin::EngineInterface,
+        call: &nu_plugin::EvaluatedCall,
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
+        (self.runner)(call, input, &self.compression)
+    }
+}
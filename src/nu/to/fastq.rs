@@ -1,10 +1,10 @@
 use crate::bio::to_fastq;
-use nu_plugin::SimplePluginCommand;
+use nu_plugin::PluginCommand;
 use nu_protocol::{Signature, Type};
 
 pub struct Command;
 
-impl SimplePluginCommand for Command {
+impl PluginCommand for Command {
     type Plugin = crate::Bio;
 
     fn name(&self) -> &str {
@@ -16,8 +16,8 @@ impl SimplePluginCommand for Command {
     }
 
     fn signature(&self) -> nu_protocol::Signature {
-        Signature::build(<Self as SimplePluginCommand>::name(self))
-            .input_output_types(vec![(Type::String, Type::table())])
+        Signature::build(<Self as PluginCommand>::name(self))
+            .input_output_types(vec![(Type::table(), Type::String)])
             .category(nu_protocol::Category::Formats)
     }
 
@@ -26,8 +26,8 @@ impl SimplePluginCommand for Command {
         _plugin: &Self::Plugin,
         _engine: &nu_plugin::EngineInterface,
         call: &nu_plugin::EvaluatedCall,
-        input: &nu_protocol::Value,
-    ) -> Result<nu_protocol::Value, nu_protocol::LabeledError> {
+        input: nu_protocol::PipelineData,
+    ) -> Result<nu_protocol::PipelineData, nu_protocol::LabeledError> {
         to_fastq(call, input)
     }
 }
@@ -1,2 +1,5 @@
+pub mod bam;
+pub mod bcf;
 pub mod fasta;
 pub mod fastq;
+pub mod sam;
@@ -0,0 +1,59 @@
+//! Splitting annotation fields with `--annotations`, using the subfield names in the header.
+
+mod common;
+
+use common::{eval, get, rows};
+use nu_protocol::{PipelineData, Value};
+
+fn vcf(description: &str) -> PipelineData {
+    let vcf = format!(
+        "##fileformat=VCFv4.3
+##INFO=<ID=ANN,Number=.,Type=String,Description=\"{description}\">
+##contig=<ID=chr1,length=1000>
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO
+chr1\t10\t.\tA\tG\t.\tPASS\tANN=G|missense_variant|MODERATE,G||LOW
+"
+    );
+    PipelineData::Value(Value::test_string(vcf), None)
+}
+
+#[test]
+fn subfield_names_from_quotes() {
+    let rows = rows(
+        "from vcf --annotations",
+        vcf("Functional annotations: 'Allele | Annotation | Annotation_Impact' "),
+    );
+
+    assert_eq!(
+        get(&rows[0], "info.ANN.0.Annotation"),
+        Value::test_string("missense_variant")
+    );
+    assert_eq!(
+        get(&rows[0], "info.ANN.1.Annotation"),
+        Value::test_nothing()
+    );
+    assert_eq!(
+        get(&rows[0], "info.ANN.1.Annotation_Impact"),
+        Value::test_string("LOW")
+    );
+}
+
+#[test]
+fn subfield_names_after_format() {
+    let rows = rows(
+        "from vcf --annotations",
+        vcf("Consequence annotations. Format: Allele|Consequence|IMPACT"),
+    );
+
+    assert_eq!(
+        get(&rows[0], "info.ANN.0.IMPACT"),
+        Value::test_string("MODERATE")
+    );
+}
+
+#[test]
+fn no_subfield_names_is_an_error() {
+    let error = eval("from vcf --annotations", vcf("Functional annotations")).unwrap_err();
+
+    assert!(format!("{error:?}").contains("INFO field ANN"), "{error:?}");
+}
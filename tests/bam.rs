@@ -0,0 +1,176 @@
+//! The flags, positions, CIGAR and auxiliary data of alignments, as typed columns, and the
+//! columns derived from them.
+
+mod common;
+
+use common::{get, open, rows};
+use nu_protocol::{PipelineData, Value};
+
+/// The same alignment, with 1 mismatch, a 2 base insertion and a 1 base deletion, given by
+/// `NM`, by `MD`, and by neither.
+const SAM: &str = "@HD\tVN:1.6\tSO:unsorted
+@SQ\tSN:chr1\tLN:1000
+nm\t0\tchr1\t1\t60\t10M2I8M1D5M\t*\t0\t0\tACGTACGTACGTACGTACGTACGTA\t*\tNM:i:4
+md\t0\tchr1\t1\t60\t10M2I8M1D5M\t*\t0\t0\tACGTACGTACGTACGTACGTACGTA\t*\tMD:Z:5A12^C5
+neither\t0\tchr1\t1\t60\t10M2I8M1D5M\t*\t0\t0\tACGTACGTACGTACGTACGTACGTA\t*
+";
+
+/// A paired, reverse, secondary alignment (`0x113`), hard clipped by 3 bases at the start and
+/// soft clipped by 2 at the start and 4 at the end.
+const CLIPPED: &str = "@HD\tVN:1.6\tSO:unsorted
+@SQ\tSN:chr1\tLN:1000
+clipped\t275\tchr1\t5\t60\t3H2S10M4S\t=\t100\t120\tACGTACGTACGTACGT\t*
+";
+
+fn clipped() -> Value {
+    rows(
+        "from sam",
+        PipelineData::Value(Value::test_string(CLIPPED), None),
+    )
+    .remove(0)
+}
+
+#[test]
+fn flags() {
+    let rows = rows("from bam", open("map.bam"));
+    // sequence-1 is mapped, sequence-24 isn't
+    assert_eq!(get(&rows[0], "flags"), Value::test_int(0));
+    assert_eq!(get(&rows[0], "flag.unmapped"), Value::test_bool(false));
+    assert_eq!(
+        get(&rows[23], "read_name"),
+        Value::test_string("sequence-24")
+    );
+    assert_eq!(get(&rows[23], "flags"), Value::test_int(4));
+    assert_eq!(get(&rows[23], "flag.unmapped"), Value::test_bool(true));
+
+    let clipped = clipped();
+    assert_eq!(get(&clipped, "flags"), Value::test_int(0x113));
+    for (flag, set) in [
+        ("paired", true),
+        ("proper_pair", true),
+        ("unmapped", false),
+        ("mate_unmapped", false),
+        ("reverse", true),
+        ("mate_reverse", false),
+        ("first", false),
+        ("last", false),
+        ("secondary", true),
+        ("qc_fail", false),
+        ("duplicate", false),
+        ("supplementary", false),
+    ] {
+        assert_eq!(
+            get(&clipped, &format!("flag.{flag}")),
+            Value::test_bool(set),
+            "{flag}"
+        );
+    }
+}
+
+#[test]
+fn positions() {
+    let rows = rows("from bam", open("map.bam"));
+    // 1-based, as in map.sam
+    assert_eq!(get(&rows[0], "alignment_start"), Value::test_int(18677));
+    assert_eq!(get(&rows[0], "mapping_quality"), Value::test_int(60));
+    assert_eq!(get(&rows[0], "mate_alignment_start"), Value::test_nothing());
+    assert_eq!(get(&rows[23], "reference_sequence"), Value::test_nothing());
+    assert_eq!(get(&rows[23], "alignment_start"), Value::test_nothing());
+
+    let clipped = clipped();
+    assert_eq!(get(&clipped, "alignment_start"), Value::test_int(5));
+    assert_eq!(
+        get(&clipped, "mate_reference_sequence"),
+        Value::test_string("chr1")
+    );
+    assert_eq!(get(&clipped, "mate_alignment_start"), Value::test_int(100));
+    assert_eq!(get(&clipped, "template_length"), Value::test_int(120));
+}
+
+#[test]
+fn tags() {
+    let tagged = rows("from bam --tags [NM de tp XX]", open("map.bam"));
+    let row = &tagged[1];
+
+    // typed by their SAM type: i, f and A
+    assert_eq!(get(row, "data.NM"), Value::test_int(12));
+    assert_eq!(get(row, "data.de"), Value::test_float(0.0012));
+    assert_eq!(get(row, "data.tp"), Value::test_string("P"));
+    // and the tags asked for are columns too, null where missing
+    assert_eq!(get(row, "NM"), Value::test_int(12));
+    assert_eq!(get(row, "de"), Value::test_float(0.0012));
+    assert_eq!(get(row, "XX"), Value::test_nothing());
+    assert_eq!(get(&tagged[23], "NM"), Value::test_nothing());
+
+    let columns = rows("from bam --tags NM,AS", open("map.bam"));
+    assert_eq!(get(&columns[1], "AS"), Value::test_int(6508));
+}
+
+fn cigar(ops: &[(&str, i64)]) -> Value {
+    Value::test_list(
+        ops.iter()
+            .map(|&(op, len)| {
+                Value::test_record(nu_protocol::record! {
+                    "op" => Value::test_string(op),
+                    "len" => Value::test_int(len),
+                })
+            })
+            .collect(),
+    )
+}
+
+#[test]
+fn cigar_ops() {
+    let rows = rows("from bam", open("map.bam"));
+    assert_eq!(
+        get(&rows[1], "cigar"),
+        cigar(&[
+            ("M", 932),
+            ("D", 3),
+            ("M", 1),
+            ("D", 3),
+            ("M", 1076),
+            ("D", 3),
+            ("M", 1),
+            ("D", 3),
+            ("M", 1264),
+        ])
+    );
+    assert_eq!(get(&rows[1], "alignment_end"), Value::test_int(18725));
+    assert_eq!(get(&rows[1], "reference_length"), Value::test_int(3286));
+    assert_eq!(get(&rows[1], "query_length"), Value::test_int(3274));
+    assert_eq!(get(&rows[23], "cigar"), cigar(&[]));
+    assert_eq!(get(&rows[23], "alignment_end"), Value::test_nothing());
+}
+
+#[test]
+fn clips() {
+    let clipped = clipped();
+    assert_eq!(
+        get(&clipped, "cigar"),
+        cigar(&[("H", 3), ("S", 2), ("M", 10), ("S", 4)])
+    );
+    assert_eq!(get(&clipped, "hard_clip_start"), Value::test_int(3));
+    assert_eq!(get(&clipped, "soft_clip_start"), Value::test_int(2));
+    assert_eq!(get(&clipped, "soft_clip_end"), Value::test_int(4));
+    assert_eq!(get(&clipped, "hard_clip_end"), Value::test_int(0));
+    assert_eq!(get(&clipped, "alignment_end"), Value::test_int(14));
+    assert_eq!(get(&clipped, "query_length"), Value::test_int(10));
+
+    let rows = rows("from bam", open("map.bam"));
+    assert_eq!(get(&rows[0], "soft_clip_start"), Value::test_int(0));
+    assert_eq!(get(&rows[0], "hard_clip_end"), Value::test_int(0));
+}
+
+#[test]
+fn percent_identity_from_nm_or_md() {
+    let rows = rows(
+        "from sam",
+        PipelineData::Value(Value::test_string(SAM), None),
+    );
+    let expected = Value::test_float(100.0 * (1.0 - 4.0 / 26.0));
+
+    assert_eq!(get(&rows[0], "percent_identity"), expected);
+    assert_eq!(get(&rows[1], "percent_identity"), expected);
+    assert_eq!(get(&rows[2], "percent_identity"), Value::test_nothing());
+}
@@ -0,0 +1,199 @@
+//! `from bcf` gives the same rows as `from vcf.gz` for the same variants.
+//!
+//! `variants.bcf` was written with `open variants.vcf | to bcf`, and `variants.vcf.gz` is
+//! `variants.vcf` bgzipped.
+
+mod common;
+
+use std::io::{Read, Write};
+
+use common::{eval, get, open, rows};
+use nu_protocol::{PipelineData, Value};
+
+fn assert_same_rows(bcf: &str, vcf_gz: &str, flags: &str) {
+    let from_bcf = rows(&format!("from bcf {flags}"), open(bcf));
+    let from_vcf = rows(&format!("from vcf.gz {flags}"), open(vcf_gz));
+
+    assert!(!from_bcf.is_empty());
+    assert_eq!(
+        from_bcf, from_vcf,
+        "{bcf} and {vcf_gz} differ with `{flags}`"
+    );
+}
+
+#[test]
+fn bcf_matches_vcf_gz() {
+    assert_same_rows("map.bcf", "map.vcf.gz", "");
+    assert_same_rows("variants.bcf", "variants.vcf.gz", "");
+}
+
+#[test]
+fn bcf_matches_vcf_gz_long() {
+    assert_same_rows("map.bcf", "map.vcf.gz", "--long");
+    assert_same_rows("variants.bcf", "variants.vcf.gz", "--long");
+}
+
+#[test]
+fn bcf_matches_vcf_gz_samples() {
+    assert_same_rows("variants.bcf", "variants.vcf.gz", "--samples [C A]");
+    assert_same_rows("variants.bcf", "variants.vcf.gz", "--samples []");
+}
+
+#[test]
+fn bcf_missing_values() {
+    let rows = rows("from bcf", open("variants.bcf"));
+
+    assert_eq!(get(&rows[1], "qual"), Value::test_string(""));
+    assert_eq!(get(&rows[0], "info.END"), Value::test_nothing());
+    assert_eq!(get(&rows[0], "genotypes.B.DP"), Value::test_nothing());
+    assert_eq!(get(&rows[0], "genotypes.C.XQ"), Value::test_nothing());
+    assert_eq!(
+        get(&rows[1], "genotypes.C.GT.alleles.0"),
+        Value::test_nothing()
+    );
+    assert_eq!(get(&rows[4], "info.AF.1"), Value::test_nothing());
+}
+
+#[test]
+fn bcf_multi_allelic() {
+    let rows = rows("from bcf", open("variants.bcf"));
+
+    assert_eq!(get(&rows[4], "alt"), Value::test_string("A,ACGT,T"));
+    assert_eq!(
+        get(&rows[0], "genotypes.A.PL"),
+        Value::test_list((0..6).map(Value::test_int).collect())
+    );
+    assert_eq!(
+        get(&rows[4], "genotypes.A.GT.alleles.1"),
+        Value::test_int(3)
+    );
+}
+
+#[test]
+fn bcf_end_and_rlen() {
+    let rows = rows("from bcf", open("variants.bcf"));
+
+    // the length of the reference allele, as with `from vcf`, not how far END reaches
+    assert_eq!(get(&rows[2], "rlen"), Value::test_int(1));
+    assert_eq!(get(&rows[2], "info.END"), Value::test_int(1500));
+    assert_eq!(get(&rows[4], "rlen"), Value::test_int(20));
+}
+
+#[test]
+fn bcf_phasing() {
+    let rows = rows("from bcf", open("variants.bcf"));
+
+    assert_eq!(
+        get(&rows[0], "genotypes.A.GT.phased"),
+        Value::test_bool(true)
+    );
+    assert_eq!(
+        get(&rows[0], "genotypes.C.GT.phased"),
+        Value::test_bool(false)
+    );
+    assert_eq!(
+        get(&rows[2], "genotypes.C.GT.phased"),
+        Value::test_bool(true)
+    );
+    assert_eq!(
+        get(&rows[2], "genotypes.C.GT.alleles.0"),
+        Value::test_nothing()
+    );
+}
+
+/// A BCF with a sample whose FORMAT string isn't UTF-8, so decoding it fails.
+fn bcf_with_bad_sample() -> Vec<u8> {
+    let vcf = "##fileformat=VCFv4.3
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+##FORMAT=<ID=XS,Number=1,Type=String,Description=\"A string\">
+##contig=<ID=chr1,length=1000>
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tA\tB
+chr1\t10\t.\tA\tG\t.\tPASS\t.\tGT:XS\t0/1:aaaa\t1/1:zzzz
+";
+    let record = eval(
+        "from vcf --header",
+        PipelineData::Value(Value::test_string(vcf), None),
+    )
+    .unwrap();
+    let bcf = eval("to bcf", PipelineData::Value(record, None))
+        .unwrap()
+        .coerce_into_binary()
+        .unwrap();
+
+    let mut raw = bgzf_decompress(&bcf);
+    let b = raw.windows(4).position(|w| w == b"zzzz").unwrap();
+    raw[b] = 0xff;
+    bgzf_compress(&raw)
+}
+
+fn bgzf_decompress(bytes: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::new();
+    noodles::bgzf::Reader::new(bytes)
+        .read_to_end(&mut raw)
+        .unwrap();
+    raw
+}
+
+fn bgzf_compress(raw: &[u8]) -> Vec<u8> {
+    let mut writer = noodles::bgzf::Writer::new(Vec::new());
+    writer.write_all(raw).unwrap();
+    writer.finish().unwrap()
+}
+
+#[test]
+fn bcf_only_decodes_samples_being_kept() {
+    let bcf = || PipelineData::Value(Value::test_binary(bcf_with_bad_sample()), None);
+
+    let kept = rows("from bcf --samples [A]", bcf());
+    assert_eq!(get(&kept[0], "genotypes.A.XS"), Value::test_string("aaaa"));
+    let long = rows("from bcf --long --samples [A]", bcf());
+    assert_eq!(get(&long[0], "gt"), Value::test_string("0/1"));
+    let sites = rows("from bcf --samples []", bcf());
+    assert_eq!(get(&sites[0], "pos"), Value::test_int(10));
+
+    // B is only decoded when it's kept
+    let all = eval("from bcf", bcf()).unwrap().into_list().unwrap();
+    match &all[0] {
+        Value::Error { error, .. } => {
+            assert!(format!("{error:?}").contains("utf-8"), "{error:?}")
+        }
+        row => panic!("expected an error decoding B, got {row:?}"),
+    }
+}
+
+#[test]
+fn bcf_truncated_in_record_lengths() {
+    let raw = bgzf_decompress(&std::fs::read(common::data("map.bcf")).unwrap());
+    let u32_at = |i: usize| u32::from_le_bytes(raw[i..i + 4].try_into().unwrap()) as usize;
+    // magic and version, the header's length and the header
+    let header_len = 5 + 4 + u32_at(5);
+    let record_len = 8 + u32_at(header_len) + u32_at(header_len + 4);
+
+    // a file ending between records is fine
+    let whole = bgzf_compress(&raw[..header_len + record_len]);
+    let records = rows(
+        "from bcf",
+        PipelineData::Value(Value::test_binary(whole), None),
+    );
+    assert_eq!(records.len(), 1);
+
+    // but not partway into the next one's lengths
+    let cut = bgzf_compress(&raw[..header_len + record_len + 3]);
+    let records = eval(
+        "from bcf",
+        PipelineData::Value(Value::test_binary(cut), None),
+    )
+    .unwrap()
+    .into_list()
+    .unwrap();
+    assert_eq!(records.len(), 2);
+    match &records[1] {
+        Value::Error { error, .. } => {
+            assert!(
+                format!("{error:?}").contains("truncated BCF record"),
+                "{error:?}"
+            )
+        }
+        row => panic!("expected an error, got {row:?}"),
+    }
+}
@@ -0,0 +1,78 @@
+//! Running the plugin's commands on the files in `tests/`.
+
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use nu_plugin_test_support::PluginTest;
+use nu_protocol::{
+    ByteStream, DataSource, PipelineData, PipelineMetadata, ShellError, Signals, Span, Value,
+};
+
+/// The `tests/` directory.
+fn tests_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+/// The path to a file in `tests/`.
+pub fn data(name: &str) -> PathBuf {
+    tests_dir().join(name)
+}
+
+/// A file as `open --raw` gives it, a byte stream which knows where it came from.
+pub fn open(name: &str) -> PipelineData {
+    let path = data(name);
+    let file = File::open(&path).unwrap();
+    let metadata = PipelineMetadata {
+        data_source: DataSource::FilePath(path),
+        content_type: None,
+    };
+
+    PipelineData::ByteStream(
+        ByteStream::file(file, Span::test_data(), Signals::empty()),
+        Some(metadata),
+    )
+}
+
+/// Run a pipeline on some input, from `tests/`.
+pub fn eval(command: &str, input: PipelineData) -> Result<Value, Box<ShellError>> {
+    let mut test = PluginTest::new("bio", nu_plugin_bio::Bio.into()).unwrap();
+    let pwd = tests_dir().display().to_string();
+    test.engine_state_mut()
+        .add_env_var("PWD".into(), Value::test_string(pwd));
+
+    let value = match test.eval_with(command, input) {
+        Ok(data) => data.into_value(Span::test_data())?,
+        Err(e) => return Err(Box::new(e)),
+    };
+    match value {
+        Value::Error { error, .. } => Err(error),
+        value => Ok(value),
+    }
+}
+
+/// The rows a command streams, failing if any of them is an error.
+pub fn rows(command: &str, input: PipelineData) -> Vec<Value> {
+    let value = eval(command, input).unwrap();
+    value
+        .into_list()
+        .unwrap()
+        .into_iter()
+        .map(|row| match row {
+            Value::Error { error, .. } => panic!("{command}: {error:?}"),
+            row => row,
+        })
+        .collect()
+}
+
+/// Get a cell, e.g. `info.END`, from a row.
+pub fn get(row: &Value, path: &str) -> Value {
+    path.split('.').fold(row.clone(), |value, key| {
+        let value = match key.parse::<usize>() {
+            Ok(i) => value.into_list().unwrap().swap_remove(i),
+            Err(_) => value.into_record().unwrap().get(key).cloned().unwrap(),
+        };
+        value
+    })
+}
@@ -0,0 +1,83 @@
+//! A truncated or corrupt CRAM ends its stream with an error, rather than hanging or ending
+//! early as if the file were shorter.
+
+mod common;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use common::{data, eval, get, open, rows};
+use nu_protocol::{PipelineData, Value};
+
+/// The rows of `from cram` on some bytes, failing if the stream doesn't end.
+fn from_cram(bytes: Vec<u8>, flags: &'static str) -> Vec<Value> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let input = PipelineData::Value(Value::test_binary(bytes), None);
+        let _ = tx.send(eval(&format!("from cram {flags}"), input));
+    });
+
+    let value = rx
+        .recv_timeout(Duration::from_secs(60))
+        .expect("from cram did not finish")
+        .unwrap();
+    value.into_list().unwrap()
+}
+
+/// Check that the stream ends with an error about a data container.
+fn assert_ends_with_error(rows: &[Value]) {
+    match rows.last() {
+        Some(Value::Error { error, .. }) => {
+            let error = format!("{error:?}");
+            assert!(error.contains("data container"), "{error}");
+        }
+        last => panic!("stream ended without an error, after {last:?}"),
+    }
+}
+
+/// Each read's sequence, by its name and where it's aligned.
+fn sequences(rows: &[Value]) -> Vec<(Value, Value, Value)> {
+    let mut sequences: Vec<_> = rows
+        .iter()
+        .map(|row| {
+            (
+                get(row, "read_name"),
+                get(row, "alignment_start"),
+                get(row, "sequence"),
+            )
+        })
+        .collect();
+    sequences
+        .sort_by_key(|(name, start, _)| (name.as_str().unwrap().to_string(), start.as_int().ok()));
+    sequences
+}
+
+#[test]
+fn cram_decodes() {
+    let cram = rows("from cram --reference drAilAlti1.fa", open("map.cram"));
+    let bam = rows("from bam", open("map.bam"));
+    assert_eq!(cram.len(), 100);
+
+    // map.cram is map.bam, so the reads are rebuilt from the reference as they were
+    assert_eq!(sequences(&cram), sequences(&bam));
+}
+
+#[test]
+fn truncated_cram_ends_with_error() {
+    let mut bytes = std::fs::read(data("map.cram")).unwrap();
+    bytes.truncate(bytes.len() / 2);
+
+    assert_ends_with_error(&from_cram(bytes.clone(), ""));
+    assert_ends_with_error(&from_cram(bytes, "--reference drAilAlti1.fa"));
+}
+
+#[test]
+fn corrupt_cram_ends_with_error() {
+    let mut bytes = std::fs::read(data("map.cram")).unwrap();
+    let middle = bytes.len() / 2;
+    bytes[middle] ^= 0xff;
+
+    assert_ends_with_error(&from_cram(bytes.clone(), ""));
+    assert_ends_with_error(&from_cram(bytes, "--reference drAilAlti1.fa"));
+}
@@ -0,0 +1,103 @@
+//! `bio faidx`, with an existing index, building one, and sequences which aren't text.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use common::{data, eval, get, rows};
+use nu_protocol::{PipelineData, Value};
+
+/// A directory of its own for a test to write FASTA files and their indexes to.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn faidx(args: &str) -> Result<Value, Box<nu_protocol::ShellError>> {
+    eval(&format!("bio faidx {args}"), PipelineData::Empty)
+}
+
+#[test]
+fn index_rows() {
+    let index = faidx("drAilAlti1.fa").unwrap();
+
+    assert_eq!(
+        index,
+        Value::test_list(vec![Value::test_record(nu_protocol::record! {
+            "name" => Value::test_string("drAilAlti1"),
+            "length" => Value::test_int(19709),
+            "offset" => Value::test_int(12),
+            "line_bases" => Value::test_int(19709),
+            "line_width" => Value::test_int(19710),
+        })])
+    );
+}
+
+#[test]
+fn regions() {
+    let rows = rows(
+        "bio faidx drAilAlti1.fa drAilAlti1:1-20 drAilAlti1:19700-19709",
+        PipelineData::Empty,
+    );
+    let fasta = fs::read_to_string(data("drAilAlti1.fa")).unwrap();
+    let sequence = fasta.lines().nth(1).unwrap();
+
+    assert_eq!(get(&rows[0], "id"), Value::test_string("drAilAlti1:1-20"));
+    assert_eq!(
+        get(&rows[0], "sequence"),
+        Value::test_string(&sequence[..20])
+    );
+    assert_eq!(
+        get(&rows[1], "sequence"),
+        Value::test_string(&sequence[19699..])
+    );
+}
+
+#[test]
+fn build_writes_the_index() {
+    let dir = scratch_dir("faidx_build");
+    let fasta = dir.join("fasta_to_map.fa");
+    fs::copy(data("fasta_to_map.fa"), &fasta).unwrap();
+    let fasta = fasta.display();
+
+    let error = faidx(&format!("{fasta}")).unwrap_err();
+    assert!(
+        format!("{error:?}").contains("no .fai index found"),
+        "{error:?}"
+    );
+
+    let built = faidx(&format!("--build {fasta}")).unwrap();
+    let built_rows = built.as_list().unwrap();
+    assert_eq!(built_rows.len(), 100);
+    assert_eq!(
+        get(&built_rows[0], "name"),
+        Value::test_string("sequence-1")
+    );
+    assert!(dir.join("fasta_to_map.fa.fai").exists());
+
+    // the index written is the one read next time
+    assert_eq!(faidx(&format!("{fasta}")).unwrap(), built);
+    let rows = rows(
+        &format!("bio faidx {fasta} sequence-1:1-10"),
+        PipelineData::Empty,
+    );
+    assert_eq!(get(&rows[0], "sequence"), Value::test_string("CGTTCTGTAA"));
+}
+
+#[test]
+fn non_utf8_sequence_is_an_error() {
+    let dir = scratch_dir("faidx_non_utf8");
+    let fasta = dir.join("bad.fa");
+    fs::write(&fasta, b">bad\nACGT\xffACGT\n").unwrap();
+
+    let value = faidx(&format!("--build {} bad:1-9", fasta.display())).unwrap();
+    match &value.as_list().unwrap()[0] {
+        Value::Error { error, .. } => {
+            assert!(format!("{error:?}").contains("UTF-8"), "{error:?}")
+        }
+        row => panic!("expected an error, got {row:?}"),
+    }
+}
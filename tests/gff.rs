@@ -0,0 +1,120 @@
+//! The directives `from gff --header` returns alongside the features, the features' columns
+//! and attributes, and gzipped GFF.
+
+mod common;
+
+use common::{eval, get, rows};
+use nu_protocol::{PipelineData, Value};
+
+const GFF: &str = "##gff-version 3
+##sequence-region contig_1 1 100
+##sequence-region contig_2 1 50
+##sequence-region contig_1 1 200
+contig_1\t.\tgene\t1\t10\t.\t+\t.\tID=gene_1
+";
+
+fn input() -> PipelineData {
+    PipelineData::Value(Value::test_string(GFF), None)
+}
+
+#[test]
+fn repeated_sequence_region_replaces_earlier() {
+    let record = eval("from gff --header", input()).unwrap();
+    let regions = get(&record, "directives.sequence_region");
+
+    assert_eq!(regions.as_record().unwrap().columns().count(), 2);
+    assert_eq!(get(&regions, "contig_1.end"), Value::test_int(200));
+    assert_eq!(get(&regions, "contig_2.end"), Value::test_int(50));
+}
+
+#[test]
+fn features_stream_without_header() {
+    let rows = rows("from gff", input());
+    let record = eval("from gff --header", input()).unwrap();
+
+    assert_eq!(rows.len(), 1);
+    assert_eq!(get(&record, "features"), Value::test_list(rows));
+}
+
+#[test]
+fn gzipped_and_bgzipped() {
+    use std::io::Write;
+
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(GFF.as_bytes()).unwrap();
+    let gzip = gzip.finish().unwrap();
+
+    let mut bgzf = noodles::bgzf::Writer::new(Vec::new());
+    bgzf.write_all(GFF.as_bytes()).unwrap();
+    let bgzf = bgzf.finish().unwrap();
+
+    let expected = eval("from gff --header", input()).unwrap();
+    for bytes in [gzip, bgzf] {
+        let input = PipelineData::Value(Value::test_binary(bytes), None);
+        assert_eq!(eval("from gff.gz --header", input).unwrap(), expected);
+    }
+}
+
+const FEATURES: &str = "##gff-version 3
+contig_1\t.\tgene\t1\t90\t.\t+\t.\tID=gene_1;Name=a%3Bb
+contig_1\t.\tmRNA\t1\t90\t.\t+\t.\tID=mRNA_1;Parent=x,y
+contig_1\t.\tCDS\t10\t90\t0.75\t-\t2\tID=cds_1;Parent=mRNA_1
+contig_1\t.\tregion\t1\t100\t.\t?\t.\tID=region_1
+contig_1\t.\tregion\t1\t100\t.\t.\t.\tID=region_2
+";
+
+fn features() -> PipelineData {
+    PipelineData::Value(Value::test_string(FEATURES), None)
+}
+
+#[test]
+fn attributes_are_percent_decoded() {
+    let rows = rows("from gff", features());
+    assert_eq!(get(&rows[0], "attributes.Name"), Value::test_string("a;b"));
+}
+
+#[test]
+fn attributes_with_several_values_are_lists() {
+    let rows = rows("from gff", features());
+    assert_eq!(
+        get(&rows[1], "attributes.Parent"),
+        Value::test_list(vec![Value::test_string("x"), Value::test_string("y")])
+    );
+    assert_eq!(get(&rows[1], "attributes.ID"), Value::test_string("mRNA_1"));
+}
+
+#[test]
+fn attributes_as_columns() {
+    let rows = rows("from gff --attributes [ID Parent]", features());
+
+    assert_eq!(get(&rows[0], "ID"), Value::test_string("gene_1"));
+    assert_eq!(get(&rows[0], "Parent"), Value::test_nothing());
+    assert_eq!(get(&rows[1], "ID"), Value::test_string("mRNA_1"));
+    assert_eq!(
+        get(&rows[1], "Parent"),
+        Value::test_list(vec![Value::test_string("x"), Value::test_string("y")])
+    );
+    // the attributes record is kept too
+    assert_eq!(get(&rows[0], "attributes.Name"), Value::test_string("a;b"));
+}
+
+#[test]
+fn typed_columns() {
+    let rows = rows("from gff", features());
+
+    assert_eq!(get(&rows[2], "start"), Value::test_int(10));
+    assert_eq!(get(&rows[2], "score"), Value::test_float(0.75));
+    assert_eq!(get(&rows[2], "phase"), Value::test_int(2));
+    assert_eq!(get(&rows[2], "strand"), Value::test_string("-"));
+    assert_eq!(get(&rows[0], "strand"), Value::test_string("+"));
+}
+
+#[test]
+fn missing_columns_are_null() {
+    let rows = rows("from gff", features());
+
+    assert_eq!(get(&rows[3], "score"), Value::test_nothing());
+    assert_eq!(get(&rows[3], "phase"), Value::test_nothing());
+    assert_eq!(get(&rows[3], "strand"), Value::test_nothing());
+    assert_eq!(get(&rows[4], "strand"), Value::test_nothing());
+}
@@ -0,0 +1,93 @@
+//! `from gtf` on `genes.gtf`, a few GENCODE style records, and `genes.gtf.gz`, the same
+//! records gzipped.
+
+mod common;
+
+use common::{get, open, rows};
+use nu_protocol::Value;
+
+#[test]
+fn gff_columns() {
+    let rows = rows("from gtf", open("genes.gtf"));
+    let columns: Vec<_> = rows[0].as_record().unwrap().columns().cloned().collect();
+
+    assert_eq!(rows.len(), 5);
+    assert_eq!(
+        columns,
+        [
+            "ref_seq_name",
+            "source",
+            "ty",
+            "start",
+            "end",
+            "score",
+            "strand",
+            "phase",
+            "attributes"
+        ]
+    );
+    assert_eq!(get(&rows[2], "ty"), Value::test_string("exon"));
+    assert_eq!(get(&rows[2], "start"), Value::test_int(11869));
+    assert_eq!(get(&rows[2], "end"), Value::test_int(12227));
+    assert_eq!(get(&rows[2], "score"), Value::test_float(0.5));
+}
+
+#[test]
+fn quoted_attributes() {
+    let rows = rows("from gtf", open("genes.gtf"));
+
+    assert_eq!(
+        get(&rows[1], "attributes.gene_id"),
+        Value::test_string("ENSG00000223972.5")
+    );
+    assert_eq!(
+        get(&rows[1], "attributes.transcript_id"),
+        Value::test_string("ENST00000456328.2")
+    );
+}
+
+#[test]
+fn unquoted_attributes() {
+    let rows = rows("from gtf", open("genes.gtf"));
+
+    assert_eq!(get(&rows[0], "attributes.level"), Value::test_string("2"));
+    assert_eq!(
+        get(&rows[2], "attributes.exon_number"),
+        Value::test_string("1")
+    );
+}
+
+#[test]
+fn repeated_keys_are_lists() {
+    let rows = rows("from gtf", open("genes.gtf"));
+
+    assert_eq!(
+        get(&rows[1], "attributes.tag"),
+        Value::test_list(vec![
+            Value::test_string("basic"),
+            Value::test_string("Ensembl_canonical"),
+        ])
+    );
+    assert_eq!(
+        get(&rows[0], "attributes.gene_name"),
+        Value::test_string("DDX11L1")
+    );
+}
+
+#[test]
+fn missing_strand_and_frame_are_null() {
+    let rows = rows("from gtf", open("genes.gtf"));
+
+    assert_eq!(get(&rows[4], "strand"), Value::test_nothing());
+    assert_eq!(get(&rows[4], "phase"), Value::test_nothing());
+    assert_eq!(get(&rows[3], "strand"), Value::test_string("-"));
+    assert_eq!(get(&rows[3], "phase"), Value::test_int(2));
+}
+
+#[test]
+fn gzipped() {
+    assert_eq!(
+        rows("from gtf.gz", open("genes.gtf.gz")),
+        rows("from gtf", open("genes.gtf"))
+    );
+}
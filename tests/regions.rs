@@ -0,0 +1,128 @@
+//! Region queries over indexed BAM, VCF and BCF files.
+
+mod common;
+
+use common::{data, eval, get, open, rows};
+use nu_protocol::{PipelineData, Value};
+
+/// An indexed file, and the command to query it.
+const FILES: [(&str, &str); 3] = [
+    ("map_sorted.bam", "from bam"),
+    ("map.vcf.gz", "from vcf.gz"),
+    ("map.bcf", "from bcf"),
+];
+
+/// The start of a row, whether it's an alignment or a variant.
+fn start(row: &Value) -> i64 {
+    let record = row.as_record().unwrap();
+    record
+        .get("alignment_start")
+        .or_else(|| record.get("pos"))
+        .unwrap()
+        .as_int()
+        .unwrap()
+}
+
+fn query(file: &str, command: &str, regions: &str) -> Vec<Value> {
+    rows(&format!("{command} --region {regions}"), open(file))
+}
+
+#[test]
+fn single_region() {
+    for (file, command) in FILES {
+        let all = rows(command, open(file));
+        let rows = query(file, command, "drAilAlti1:1000-9000");
+
+        assert!(!rows.is_empty(), "{file}");
+        assert!(rows.len() < all.len(), "{file}");
+        assert!(rows.iter().all(|row| start(row) <= 9000), "{file}");
+    }
+}
+
+#[test]
+fn variants_in_region() {
+    let rows = query("map.vcf.gz", "from vcf.gz", "drAilAlti1:1000-9000");
+    let starts: Vec<_> = rows.iter().map(start).collect();
+
+    assert_eq!(starts, [1044, 3821, 3825, 6307, 8041]);
+    assert_eq!(get(&rows[0], "chrom"), Value::test_string("drAilAlti1"));
+}
+
+#[test]
+fn multiple_regions() {
+    for (file, command) in FILES {
+        let first = query(file, command, "drAilAlti1:1000-3000");
+        let second = query(file, command, "drAilAlti1:15000-18000");
+        let both = query(
+            file,
+            command,
+            "[drAilAlti1:15000-18000 drAilAlti1:1000-3000]",
+        );
+
+        // in the order they are in the file, whichever order the regions were given in, and
+        // long reads overlapping both only once
+        let second: Vec<_> = second
+            .into_iter()
+            .filter(|row| !first.contains(row))
+            .collect();
+        let expected: Vec<_> = first.into_iter().chain(second).collect();
+        assert_eq!(both, expected, "{file}");
+    }
+}
+
+#[test]
+fn overlapping_regions_return_records_once() {
+    for (file, command) in FILES {
+        let merged = query(file, command, "drAilAlti1:1000-9500");
+        let overlapping = query(
+            file,
+            command,
+            "[drAilAlti1:5000-9000 drAilAlti1:1000-6000 drAilAlti1:9001-9500]",
+        );
+
+        assert!(!merged.is_empty(), "{file}");
+        assert_eq!(overlapping, merged, "{file}");
+    }
+}
+
+#[test]
+fn records_spanning_two_regions_come_once() {
+    // reads are longer than the gap between the regions, so some overlap both
+    let file = "map_sorted.bam";
+    let first = query(file, "from bam", "drAilAlti1:5000-5000");
+    let second = query(file, "from bam", "drAilAlti1:5010-5010");
+    let both = query(
+        file,
+        "from bam",
+        "[drAilAlti1:5000-5000 drAilAlti1:5010-5010]",
+    );
+
+    let shared = first.iter().filter(|row| second.contains(row)).count();
+    assert!(shared > 0);
+    assert_eq!(both.len(), first.len() + second.len() - shared);
+}
+
+#[test]
+fn unknown_reference_sequence() {
+    for (file, command) in FILES {
+        let error = eval(&format!("{command} --region nope:1-10"), open(file)).unwrap_err();
+        assert!(
+            format!("{error:?}").contains("reference sequence nope is not in the index"),
+            "{file}: {error:?}"
+        );
+    }
+}
+
+#[test]
+fn region_needs_a_file() {
+    for (file, command) in FILES {
+        let bytes = std::fs::read(data(file)).unwrap();
+        let input = PipelineData::Value(Value::test_binary(bytes), None);
+
+        let error = eval(&format!("{command} --region drAilAlti1:1-10"), input).unwrap_err();
+        assert!(
+            format!("{error:?}").contains("need the input to be opened from a file"),
+            "{file}: {error:?}"
+        );
+    }
+}
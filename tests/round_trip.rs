@@ -0,0 +1,117 @@
+//! Writing what `from ... --header` reads, and reading it back, gives the same header and
+//! records.
+
+mod common;
+
+use common::{eval, get, open};
+use nu_protocol::{PipelineData, Value};
+
+/// Read a file with `from --header`, write it with `to`, and read it back with `back --header`.
+fn round_trip(file: &str, from: &str, to: &str, back: &str) -> (Value, Value) {
+    let record = eval(&format!("{from} --header"), open(file)).unwrap();
+    let written = eval(to, PipelineData::Value(record.clone(), None)).unwrap();
+    let read = eval(
+        &format!("{back} --header"),
+        PipelineData::Value(written, None),
+    )
+    .unwrap();
+
+    (record, read)
+}
+
+#[test]
+fn bam_to_bam() {
+    let (record, read) = round_trip("map.bam", "from bam", "to bam", "from bam");
+    assert!(!get(&record, "body").into_list().unwrap().is_empty());
+    assert_eq!(read, record);
+}
+
+#[test]
+fn bam_to_sam() {
+    let (record, read) = round_trip("map.bam", "from bam", "to sam", "from sam");
+    assert_eq!(read, record);
+}
+
+#[test]
+fn missing_header_fields_are_null() {
+    let record = eval("from bam --header", open("map.bam")).unwrap();
+    let sequence = get(&record, "header.reference_sequences.drAilAlti1");
+    assert_eq!(get(&sequence, "md5"), Value::test_nothing());
+
+    let written = eval("to sam", PipelineData::Value(record, None)).unwrap();
+    let sam = String::from_utf8(written.coerce_into_binary().unwrap()).unwrap();
+    assert!(sam.starts_with("@HD\tVN:1.6\tSO:unknown\tGO:none\n@SQ\tSN:drAilAlti1\tLN:19709\n"));
+}
+
+#[test]
+fn float_arrays_take_ints() {
+    let mut record = eval("from bam --header", open("map.bam"))
+        .unwrap()
+        .into_record()
+        .unwrap();
+    let body = record.get("body").unwrap().clone().into_list().unwrap();
+    let mut alignment = body[0].clone().into_record().unwrap();
+    let mut data = alignment
+        .get("data")
+        .unwrap()
+        .clone()
+        .into_record()
+        .unwrap();
+    data.insert(
+        "XF",
+        Value::test_list(vec![Value::test_int(1), Value::test_float(0.5)]),
+    );
+    alignment.insert("data", Value::test_record(data));
+    record.insert(
+        "body",
+        Value::test_list(vec![Value::test_record(alignment)]),
+    );
+
+    let input = PipelineData::Value(Value::test_record(record), None);
+    let written = eval("to bam", input).unwrap();
+    let read = eval("from bam --header", PipelineData::Value(written, None)).unwrap();
+
+    assert_eq!(
+        get(&read, "body.0.data.XF"),
+        Value::test_list(vec![Value::test_float(1.0), Value::test_float(0.5)])
+    );
+}
+
+#[test]
+fn vcf_to_vcf() {
+    for file in ["map.vcf.gz", "variants.vcf.gz"] {
+        let (record, read) = round_trip(file, "from vcf.gz", "to vcf", "from vcf");
+        assert!(!get(&record, "body").into_list().unwrap().is_empty());
+        assert_eq!(read, record, "{file}");
+
+        let (record, read) = round_trip(file, "from vcf.gz", "to vcf.gz", "from vcf.gz");
+        assert_eq!(read, record, "{file}");
+    }
+}
+
+#[test]
+fn bcf_to_bcf() {
+    for file in ["map.bcf", "variants.bcf"] {
+        let (record, read) = round_trip(file, "from bcf", "to bcf", "from bcf");
+        assert!(!get(&record, "body").into_list().unwrap().is_empty());
+        assert_eq!(read, record, "{file}");
+    }
+}
+
+#[test]
+fn string_tags_stay_strings() {
+    let sam = "@HD\tVN:1.6\tSO:unsorted
+@SQ\tSN:chr1\tLN:1000
+@RG\tID:1
+read\t0\tchr1\t1\t60\t4M\t*\t0\t0\tACGT\t*\tRG:Z:1\ttp:A:P\tXZ:Z:a
+";
+    let record = eval(
+        "from sam --header",
+        PipelineData::Value(Value::test_string(sam), None),
+    )
+    .unwrap();
+    let written = eval("to sam", PipelineData::Value(record, None)).unwrap();
+    let written = String::from_utf8(written.coerce_into_binary().unwrap()).unwrap();
+
+    assert!(written.ends_with("\tRG:Z:1\ttp:A:P\tXZ:Z:a\n"), "{written}");
+}
@@ -0,0 +1,40 @@
+//! Records are streamed by default, and formats with a header return a record of the header
+//! and the body with `--header`.
+
+mod common;
+
+use common::{eval, get, open, rows};
+use nu_protocol::Value;
+
+#[test]
+fn header_and_body() {
+    for (file, command) in [
+        ("map.bam", "from bam"),
+        ("map.sam", "from sam"),
+        ("map.vcf.gz", "from vcf.gz"),
+        ("map.bcf", "from bcf"),
+        ("map.cram", "from cram"),
+    ] {
+        let streamed = rows(command, open(file));
+        let record = eval(&format!("{command} --header"), open(file)).unwrap();
+
+        assert!(!streamed.is_empty(), "{file}");
+        assert!(get(&record, "header").as_record().is_ok(), "{file}");
+        assert_eq!(get(&record, "body"), Value::test_list(streamed), "{file}");
+    }
+}
+
+#[test]
+fn gfa_lines() {
+    let rows = rows("from gfa", open("map.gfa"));
+    let types: Vec<_> = rows
+        .iter()
+        .map(|row| get(row, "type").into_string().unwrap())
+        .collect();
+
+    assert_eq!(types[0], "header");
+    assert_eq!(get(&rows[0], "version"), Value::test_string("1.0"));
+    assert!(types.iter().any(|t| t == "segment"));
+    assert!(types.iter().any(|t| t == "link"));
+    assert_eq!(rows, common::rows("from gfa.gz", open("map.gfa.gz")));
+}
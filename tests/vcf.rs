@@ -0,0 +1,27 @@
+//! A VCF record which can't be read is an error naming the record, and where the last good
+//! one was.
+
+mod common;
+
+use std::fs;
+
+use common::{data, eval};
+use nu_protocol::{PipelineData, Value};
+
+#[test]
+fn malformed_record_is_located() {
+    let vcf = fs::read_to_string(data("variants.vcf")).unwrap();
+    let vcf = vcf.replacen("chr1\t1000\t", "chr1\tabc\t", 1);
+
+    let input = PipelineData::Value(Value::test_string(vcf), None);
+    let rows = eval("from vcf", input).unwrap().into_list().unwrap();
+
+    assert!(matches!(rows[1], Value::Record { .. }));
+    match &rows[2] {
+        Value::Error { error, .. } => {
+            let error = format!("{error:?}");
+            assert!(error.contains("record 3 (after chr1:200)"), "{error}");
+        }
+        row => panic!("expected an error, got {row:?}"),
+    }
+}